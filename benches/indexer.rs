@@ -0,0 +1,178 @@
+//! Indexing throughput on a large synthetic WARC.
+//!
+//! Run with `cargo bench --bench indexer`. The WARC is written to the
+//! temporary directory in both uncompressed and gzipped form, then each
+//! is indexed a few times and the fastest run is reported. These
+//! environment variables change the defaults:
+//!
+//! - `WACKSY_BENCH_SIZE_MIB`, the size of the uncompressed WARC (1024)
+//! - `WACKSY_BENCH_RUNS`, how many times to index each file (3)
+//! - `WACKSY_BENCH_MIN_MIB_PER_SEC`, if set, fail when indexing the
+//!   uncompressed WARC is slower than this, to catch regressions
+//!
+//! Wall-clock times on a shared machine vary a lot from one run to the
+//! next, so compare against another build by running the two in turn
+//! several times. On a 128 MiB WARC written by this benchmark, the median
+//! CPU time over 40 alternating runs was 0.216s (590 MiB/s) with 11
+//! allocations per response, against 0.248s (520 MiB/s) and 39
+//! allocations for 0.3.4. Gzipped WARCs are limited by inflating them,
+//! and index at about the same speed as before.
+
+use flate2::{Compression, write::GzEncoder};
+use std::{
+    env,
+    fs::{self, File},
+    io::{BufWriter, Write as _},
+    path::Path,
+    process::ExitCode,
+    time::{Duration, Instant},
+};
+use wacksy::indexer::indexer;
+
+/// Roughly the average size of an HTML response in a browser crawl.
+const BODY_SIZE: usize = 2048;
+
+fn main() -> ExitCode {
+    let size_mib: u32 = env_or("WACKSY_BENCH_SIZE_MIB", 1024);
+    let runs: u32 = env_or("WACKSY_BENCH_RUNS", 3);
+    let min_mib_per_sec: Option<f64> = env::var("WACKSY_BENCH_MIN_MIB_PER_SEC")
+        .ok()
+        .and_then(|value| return value.parse().ok());
+
+    let temp_dir = env::temp_dir();
+    let warc_file_path = temp_dir.join("wacksy-bench.warc");
+    let gzip_file_path = temp_dir.join("wacksy-bench.warc.gz");
+
+    println!(
+        "Writing {size_mib} MiB synthetic WARC to {}",
+        temp_dir.display()
+    );
+    let record_count = write_synthetic_warc(&warc_file_path, &gzip_file_path, size_mib);
+    println!("{record_count} records in each file");
+
+    let uncompressed_time = time_indexer(&warc_file_path, runs, record_count);
+    let gzip_time = time_indexer(&gzip_file_path, runs, record_count);
+    fs::remove_file(&warc_file_path).unwrap();
+    fs::remove_file(&gzip_file_path).unwrap();
+
+    let uncompressed_mib_per_sec = f64::from(size_mib) / uncompressed_time.as_secs_f64();
+    report("uncompressed", uncompressed_time, size_mib, record_count);
+    report("gzip", gzip_time, size_mib, record_count);
+
+    if let Some(min_mib_per_sec) = min_mib_per_sec {
+        if uncompressed_mib_per_sec < min_mib_per_sec {
+            eprintln!(
+                "Uncompressed indexing ran at {uncompressed_mib_per_sec:.0} MiB/s, below the minimum of {min_mib_per_sec:.0} MiB/s"
+            );
+            return ExitCode::FAILURE;
+        }
+    }
+    return ExitCode::SUCCESS;
+}
+
+fn env_or(name: &str, default: u32) -> u32 {
+    return env::var(name)
+        .ok()
+        .and_then(|value| return value.parse().ok())
+        .unwrap_or(default);
+}
+
+/// Write pairs of request and response records until the uncompressed
+/// WARC reaches the given size, writing the same records gzipped one
+/// member per record alongside. Returns the number of records.
+fn write_synthetic_warc(warc_file_path: &Path, gzip_file_path: &Path, size_mib: u32) -> u32 {
+    let target_size = u64::from(size_mib) << 20;
+    let mut warc_file = BufWriter::new(File::create(warc_file_path).unwrap());
+    let mut gzip_file = BufWriter::new(File::create(gzip_file_path).unwrap());
+
+    let mut written: u64 = 0;
+    let mut record_count: u32 = 0;
+    let mut page_number: u32 = 0;
+    while written < target_size {
+        let url = format!(
+            "https://www.example.com/section/{}/page-{page_number}.html",
+            page_number % 97
+        );
+        let second = page_number % 60;
+        for record in [
+            request_record(&url, second, record_count),
+            response_record(&url, second, page_number, record_count + 1),
+        ] {
+            warc_file.write_all(&record).unwrap();
+            let mut encoder = GzEncoder::new(&mut gzip_file, Compression::fast());
+            encoder.write_all(&record).unwrap();
+            encoder.finish().unwrap();
+            written += record.len() as u64;
+            record_count += 1;
+        }
+        page_number += 1;
+    }
+    warc_file.flush().unwrap();
+    gzip_file.flush().unwrap();
+    return record_count;
+}
+
+fn request_record(url: &str, second: u32, record_number: u32) -> Vec<u8> {
+    let http_block = format!(
+        "GET {url} HTTP/1.1\r\nHost: www.example.com\r\nUser-Agent: Mozilla/5.0 (X11; Linux x86_64) wacksy-bench\r\nAccept: text/html,application/xhtml+xml\r\nAccept-Language: en-GB,en;q=0.9\r\n\r\n"
+    );
+    return warc_record("request", url, second, record_number, http_block.as_bytes());
+}
+
+fn response_record(url: &str, second: u32, page_number: u32, record_number: u32) -> Vec<u8> {
+    let title = format!("<html><head><title>Page {page_number}</title></head><body><p>");
+    let mut body = title.into_bytes();
+    body.resize(BODY_SIZE - 18, b'x');
+    body.extend_from_slice(b"</p></body></html>");
+    let http_block = [
+        format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nDate: Mon, 01 Sep 2025 00:00:{second:02} GMT\r\nServer: nginx\r\nCache-Control: max-age=600\r\nETag: \"{page_number:08x}\"\r\n\r\n",
+            body.len()
+        )
+        .as_bytes(),
+        &body,
+    ]
+    .concat();
+    return warc_record("response", url, second, record_number, &http_block);
+}
+
+fn warc_record(
+    record_type: &str,
+    url: &str,
+    second: u32,
+    record_number: u32,
+    block: &[u8],
+) -> Vec<u8> {
+    let warc_header = format!(
+        "WARC/1.1\r\nWARC-Type: {record_type}\r\nWARC-Record-ID: <urn:uuid:00000000-0000-0000-0000-{record_number:012}>\r\nWARC-Target-URI: {url}\r\nWARC-Date: 2025-09-01T00:00:{second:02}Z\r\nWARC-Payload-Digest: sha1:3I42H3S6NNFQ2MSVX7XZKYAYSCX5QBYJ\r\nContent-Type: application/http; msgtype={record_type}\r\nContent-Length: {}\r\n\r\n",
+        block.len()
+    );
+    return [warc_header.as_bytes(), block, b"\r\n\r\n"].concat();
+}
+
+/// Index the file `runs` times, checking every record was
+/// indexed, and return the fastest time.
+fn time_indexer(warc_file_path: &Path, runs: u32, record_count: u32) -> Duration {
+    let mut fastest = Duration::MAX;
+    for _ in 0..runs.max(1) {
+        let start = Instant::now();
+        let index = indexer(warc_file_path).unwrap();
+        fastest = fastest.min(start.elapsed());
+        // Request records are paired with responses rather than indexed.
+        assert_eq!(
+            index.len(),
+            usize::try_from(record_count / 2).unwrap(),
+            "every response should be indexed"
+        );
+    }
+    return fastest;
+}
+
+fn report(name: &str, time: Duration, size_mib: u32, record_count: u32) {
+    let seconds = time.as_secs_f64();
+    println!(
+        "{name:>12}: {seconds:.2}s, {:.0} MiB/s, {:.0} records/s",
+        f64::from(size_mib) / seconds,
+        f64::from(record_count) / seconds
+    );
+}
@@ -6,13 +6,19 @@
 //! > and is compliant with the [FRICTIONLESS-DATA-PACKAGE](https://specs.frictionlessdata.io/data-package/) specification.
 
 use base16ct::HexDisplay;
-use chrono::Local;
+use chrono::{DateTime, Local};
+use serde_json::{Map, Value, json};
 use sha2::{Digest as _, Sha256};
 use std::{error::Error, fmt, fs, path::Path};
 
 use crate::{
-    indexer::{to_cdxj_string, to_pages_json_string, IndexRecord},
-    WACZ_VERSION,
+    WACZ_VERSION, WaczOptions,
+    indexer::{
+        COMPRESSED_INDEX_FILE_NAME, IndexRecord, SECONDARY_INDEX_FILE_NAME, SeedUrls,
+        to_pages_json_string_with_selector, to_seed_pages_json_strings, to_zipnum_index,
+        write_cdxj,
+    },
+    parallel::parallel_map,
 };
 
 /// The main datapackage struct.
@@ -26,6 +32,10 @@ pub struct DataPackage {
     pub created: String,
     /// The name of the software used to create the WACZ file, in this case `wacksy 0.3.4`.
     pub software: String,
+    /// The URL of the page the WACZ should open on.
+    pub main_page_url: Option<String>,
+    /// The capture date of the main page, in RFC 3339 format.
+    pub main_page_date: Option<String>,
     /// List of file names, paths, sizes, and fixity for all files contained in the WACZ.
     pub resources: Vec<DataPackageResource>,
 }
@@ -46,7 +56,14 @@ pub struct DataPackageResource {
 #[derive(Debug)]
 pub enum ResourceType {
     CDXJ,
+    /// The gzipped blocks of a `ZipNum` index.
+    CompressedIndex,
+    /// Where each block of the `ZipNum` index starts.
+    SecondaryIndex,
+    /// Every page, or only the seeds if there are extra pages as well.
     Pages,
+    /// Pages which aren't seeds.
+    ExtraPages,
     Warc,
 }
 
@@ -57,11 +74,11 @@ pub struct DataPackageDigest {
 }
 impl fmt::Display for DataPackageDigest {
     fn fmt(&self, message: &mut fmt::Formatter) -> fmt::Result {
-        return write!(
-            message,
-            "{{\"path\":\"{}\",\"hash\":\"{}\"}}",
-            self.path, self.hash
-        );
+        let digest = json!({
+            "path": self.path,
+            "hash": self.hash,
+        });
+        return write!(message, "{digest}");
     }
 }
 
@@ -72,6 +89,8 @@ impl Default for DataPackage {
             wacz_version: WACZ_VERSION.to_owned(),
             created: Local::now().to_rfc3339(),
             software: format!("wacksy {}", env!("CARGO_PKG_VERSION")),
+            main_page_url: None,
+            main_page_date: None,
             resources: Vec::with_capacity(512),
         };
     }
@@ -87,9 +106,25 @@ impl DataPackage {
     /// resource if there is anything wrong with the filename
     /// or path of a resource.
     pub fn new(warc_file_paths: &[&Path], index: &[IndexRecord]) -> Result<Self, DataPackageError> {
+        return Self::new_with_options(warc_file_paths, index, &WaczOptions::default());
+    }
+
+    /// # Create datapackage, with options
+    ///
+    /// Works the same as [`DataPackage::new`], but with [`WaczOptions`].
+    /// The WARC files are read and hashed on `options.threads` threads.
+    ///
+    /// # Errors
+    ///
+    /// Will return a `DataPackageError`, see [`DataPackage::new`].
+    pub fn new_with_options(
+        warc_file_paths: &[&Path],
+        index: &[IndexRecord],
+        options: &WaczOptions,
+    ) -> Result<Self, DataPackageError> {
         let mut data_package = Self::default();
 
-        for warc_file_path in warc_file_paths {
+        let warc_resources = parallel_map(warc_file_paths, options.threads, |warc_file_path| {
             let warc_file_bytes = match fs::read(warc_file_path) {
                 Ok(bytes) => bytes,
                 Err(error) => return Err(DataPackageError::FileReadError(error)),
@@ -112,32 +147,87 @@ impl DataPackage {
                 }
             };
 
-            // Add Warc file to datapackage
+            return DataPackageResource::new(ResourceType::Warc, warc_file_name, &warc_file_bytes);
+        });
+
+        // Add Warc files to datapackage, in the order they were given
+        for warc_resource in warc_resources {
+            Self::add_resource(&mut data_package, warc_resource?);
+        }
+
+        // Add CDXJ file to datapackage, sorted so that it can be searched
+        let mut cdxj_index = Vec::with_capacity(index.len() * 256);
+        if let Err(error) = write_cdxj(index, &mut cdxj_index, options.max_sort_memory) {
+            return Err(DataPackageError::IndexSortError(error));
+        }
+        if options.zipnum_index {
+            let zipnum_index = match to_zipnum_index(&cdxj_index, options.zipnum_block_size) {
+                Ok(zipnum_index) => zipnum_index,
+                Err(error) => return Err(DataPackageError::IndexCompressionError(error)),
+            };
             Self::add_resource(
                 &mut data_package,
-                DataPackageResource::new(ResourceType::Warc, warc_file_name, &warc_file_bytes)?,
+                DataPackageResource::new(
+                    ResourceType::CompressedIndex,
+                    COMPRESSED_INDEX_FILE_NAME.to_owned(),
+                    &zipnum_index.compressed_index,
+                )?,
+            );
+            Self::add_resource(
+                &mut data_package,
+                DataPackageResource::new(
+                    ResourceType::SecondaryIndex,
+                    SECONDARY_INDEX_FILE_NAME.to_owned(),
+                    zipnum_index.secondary_index.as_bytes(),
+                )?,
+            );
+        } else {
+            Self::add_resource(
+                &mut data_package,
+                DataPackageResource::new(
+                    ResourceType::CDXJ,
+                    "index.cdxj".to_string(),
+                    &cdxj_index,
+                )?,
             );
         }
 
-        // Add CDXJ file to datapackage
-        Self::add_resource(
-            &mut data_package,
-            DataPackageResource::new(
-                ResourceType::CDXJ,
-                "index.cdxj".to_string(),
-                &to_cdxj_string(index).into_bytes(),
-            )?,
-        );
+        let seed_urls = SeedUrls::new(&options.seed_urls);
+        (data_package.main_page_url, data_package.main_page_date) =
+            find_main_page(index, options, &seed_urls);
 
-        // Add Pages file to datapackage
-        Self::add_resource(
-            &mut data_package,
-            DataPackageResource::new(
-                ResourceType::Pages,
-                "pages.jsonl".to_string(),
-                &to_pages_json_string(index).into_bytes(),
-            )?,
-        );
+        // Add Pages file to datapackage, split into seeds
+        // and extra pages if we know what the seeds are.
+        if seed_urls.is_empty() {
+            Self::add_resource(
+                &mut data_package,
+                DataPackageResource::new(
+                    ResourceType::Pages,
+                    "pages.jsonl".to_string(),
+                    &to_pages_json_string_with_selector(index, &*options.page_selector)
+                        .into_bytes(),
+                )?,
+            );
+        } else {
+            let (seed_pages, extra_pages) =
+                to_seed_pages_json_strings(index, &*options.page_selector, &seed_urls);
+            Self::add_resource(
+                &mut data_package,
+                DataPackageResource::new(
+                    ResourceType::Pages,
+                    "pages.jsonl".to_string(),
+                    &seed_pages.into_bytes(),
+                )?,
+            );
+            Self::add_resource(
+                &mut data_package,
+                DataPackageResource::new(
+                    ResourceType::ExtraPages,
+                    "extraPages.jsonl".to_string(),
+                    &extra_pages.into_bytes(),
+                )?,
+            );
+        }
 
         return Ok(data_package);
     }
@@ -160,6 +250,56 @@ impl DataPackage {
         };
     }
 }
+/// Work out the URL and date of the main page, from the options if they're
+/// given, otherwise from the earliest seed page (or page, if there are no
+/// seeds) in the index.
+fn find_main_page(
+    index: &[IndexRecord],
+    options: &WaczOptions,
+    seed_urls: &SeedUrls,
+) -> (Option<String>, Option<String>) {
+    let main_page = options.main_page_url.as_ref().map_or_else(
+        || {
+            return earliest_capture(index, |record| {
+                return record.is_selected_page(&*options.page_selector)
+                    && (seed_urls.is_empty() || seed_urls.contains(record.url()));
+            });
+        },
+        |main_page_url| {
+            let main_page_url = SeedUrls::new([main_page_url]);
+            return earliest_capture(index, |record| {
+                return main_page_url.contains(record.url());
+            });
+        },
+    );
+    let main_page_url = options
+        .main_page_url
+        .clone()
+        .or_else(|| return main_page.map(|record| return record.url().to_owned()));
+    let main_page_date = options
+        .main_page_date
+        .clone()
+        .or_else(|| return main_page.map(|record| return record.timestamp().to_owned()));
+    return (main_page_url, main_page_date);
+}
+
+/// Find the earliest matching record, so that the order the WARCs are
+/// given in doesn't matter. Captures at the same time are ordered by URL.
+fn earliest_capture(
+    index: &[IndexRecord],
+    is_main_page: impl Fn(&IndexRecord) -> bool,
+) -> Option<&IndexRecord> {
+    return index
+        .iter()
+        .filter(|record| return is_main_page(record))
+        .min_by_key(|record| {
+            return (
+                DateTime::parse_from_rfc3339(record.timestamp()).ok(),
+                record.url(),
+            );
+        });
+}
+
 impl fmt::Display for DataPackage {
     //! The file should look something like this when serialised to JSON:
     //!
@@ -169,6 +309,8 @@ impl fmt::Display for DataPackage {
     //!   "wacz_version": "1.1.1",
     //!   "created": "2026-05-06T11:03:03.499792020+01:00",
     //!   "software": "wacksy 0.3.4",
+    //!   "mainPageUrl": "https://example.com/",
+    //!   "mainPageDate": "2025-04-23T12:10:42.525Z",
     //!   "resources": [
     //!     {
     //!       "name": "data.warc",
@@ -186,18 +328,36 @@ impl fmt::Display for DataPackage {
     //! }
     //! ```
     fn fmt(&self, message: &mut fmt::Formatter) -> fmt::Result {
-        let collected_resources = self
+        // Everything is written out by serde_json, so that values such
+        // as file names and URLs are quoted and escaped properly.
+        let mut datapackage = Map::with_capacity(8);
+        datapackage.insert("profile".to_owned(), Value::from("data-package"));
+        datapackage.insert(
+            "wacz_version".to_owned(),
+            Value::from(self.wacz_version.as_str()),
+        );
+        datapackage.insert("created".to_owned(), Value::from(self.created.as_str()));
+        datapackage.insert("software".to_owned(), Value::from(self.software.as_str()));
+        // The main page is left out if there isn't one.
+        if let Some(main_page_url) = &self.main_page_url {
+            datapackage.insert(
+                "mainPageUrl".to_owned(),
+                Value::from(main_page_url.as_str()),
+            );
+        }
+        if let Some(main_page_date) = &self.main_page_date {
+            datapackage.insert(
+                "mainPageDate".to_owned(),
+                Value::from(main_page_date.as_str()),
+            );
+        }
+        let resources = self
             .resources
             .iter()
-            .map(std::string::ToString::to_string)
-            .collect::<Vec<String>>()
-            .join(",");
-        // Iterate over each resource here and create datapackage
-        return write!(
-            message,
-            "{{\"profile\":\"data-package\",\"wacz_version\":\"{}\",\"created\":\"{}\",\"software\":\"{}\",\"resources\":[{collected_resources}]}}",
-            self.wacz_version, self.created, self.software
-        );
+            .map(DataPackageResource::to_json)
+            .collect::<Vec<Value>>();
+        datapackage.insert("resources".to_owned(), Value::from(resources));
+        return write!(message, "{}", Value::Object(datapackage));
     }
 }
 
@@ -221,8 +381,10 @@ impl DataPackageResource {
         // is a pretty convoluted way of doing things
         // but it works fine.
         let mut path = match resource_type {
-            ResourceType::CDXJ => "indexes/",
-            ResourceType::Pages => "pages/",
+            ResourceType::CDXJ | ResourceType::CompressedIndex | ResourceType::SecondaryIndex => {
+                "indexes/"
+            }
+            ResourceType::Pages | ResourceType::ExtraPages => "pages/",
             ResourceType::Warc => "archive/",
         }
         .to_owned();
@@ -237,19 +399,28 @@ impl DataPackageResource {
             resource_type,
         });
     }
-}
-impl fmt::Display for DataPackageResource {
-    fn fmt(&self, message: &mut fmt::Formatter) -> fmt::Result {
+
+    /// The resource as it's listed in `datapackage.json`.
+    fn to_json(&self) -> Value {
         let name = match self.resource_type {
             ResourceType::CDXJ => "crawl_index",
+            ResourceType::CompressedIndex => "compressed_index",
+            ResourceType::SecondaryIndex => "secondary_index",
             ResourceType::Pages => "pages_file",
+            ResourceType::ExtraPages => "extra_pages_file",
             ResourceType::Warc => &self.file_name,
         };
-        return write!(
-            message,
-            "{{\"name\":\"{}\",\"path\":\"{}\",\"hash\":\"{}\",\"bytes\":{}}}",
-            name, self.path, self.hash, self.bytes
-        );
+        return json!({
+            "name": name,
+            "path": self.path,
+            "hash": self.hash,
+            "bytes": self.bytes,
+        });
+    }
+}
+impl fmt::Display for DataPackageResource {
+    fn fmt(&self, message: &mut fmt::Formatter) -> fmt::Result {
+        return write!(message, "{}", self.to_json());
     }
 }
 
@@ -258,6 +429,10 @@ pub enum DataPackageError {
     FileNameError(String),
     FilePathError(String),
     FileReadError(std::io::Error),
+    /// A temporary file used to sort the index couldn't be written or read.
+    IndexSortError(std::io::Error),
+    /// A block of the `ZipNum` index couldn't be compressed.
+    IndexCompressionError(std::io::Error),
 }
 impl fmt::Display for DataPackageError {
     fn fmt(&self, message: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -271,13 +446,23 @@ impl fmt::Display for DataPackageError {
             Self::FileReadError(error_message) => {
                 return write!(message, "Could not read WARC file: {error_message}");
             }
+            Self::IndexSortError(error_message) => {
+                return write!(message, "Could not sort index: {error_message}");
+            }
+            Self::IndexCompressionError(error_message) => {
+                return write!(message, "Could not compress index: {error_message}");
+            }
         }
     }
 }
 impl Error for DataPackageError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
-            Self::FileReadError(read_error) => return Some(read_error),
+            Self::FileReadError(read_error)
+            | Self::IndexSortError(read_error)
+            | Self::IndexCompressionError(read_error) => {
+                return Some(read_error);
+            }
             Self::FilePathError(_) | Self::FileNameError(_) => return None,
         }
     }
@@ -292,12 +477,12 @@ mod tests {
 
     mod common {
         use super::DataPackage;
-        use crate::indexer;
+        use crate::indexer::indexer;
         use std::path::Path;
 
         pub fn create_datapackage() -> DataPackage {
             let warc_file_path: &Path = Path::new("tests/example.warc.gz");
-            let index = indexer(warc_file_path);
+            let index = indexer(warc_file_path).unwrap();
             return DataPackage::new(&[warc_file_path], &index).unwrap();
         }
     }
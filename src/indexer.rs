@@ -1,71 +1,429 @@
 use chrono::DateTime;
 use flate2::bufread::GzDecoder;
+use serde_json::{Map, Value};
 use std::{
+    borrow::Cow,
+    collections::HashMap,
+    error::Error,
+    fmt,
     fs::File,
-    io::{BufRead, BufReader, Read as _, Seek as _, SeekFrom},
+    io::{self, BufRead, BufReader, ErrorKind, Read as _, Seek as _, SeekFrom, Write},
     path::Path,
-    str::FromStr as _,
+    sync::Arc,
 };
+use uuid::Uuid;
+use zstd::{dict::DecoderDictionary, stream::read::Decoder as ZstdDecoder};
 
-use crate::indexer::surt::create_surt;
+pub use crate::indexer::{
+    headers::HeaderMap,
+    mime::MimeType,
+    pages::{HtmlPages, HttpGetPages, PagePolicy, PageSelector, SeedUrls},
+    surt::{SurtError, create_surt, escape_urlkey},
+    zipnum::{COMPRESSED_INDEX_FILE_NAME, SECONDARY_INDEX_FILE_NAME, ZipNumIndex},
+};
 
+mod headers;
+mod html;
+mod mime;
+mod pages;
+mod request;
+mod sort;
 mod surt;
+mod zipnum;
 
-#[must_use]
-pub fn indexer(warc_file_path: &Path) -> Vec<IndexRecord> {
+/// WARC versions which the indexer is able to read. Older crawls
+/// from tools like Heritrix and wget are usually written as WARC/1.0.
+pub const SUPPORTED_WARC_VERSIONS: [&str; 2] = ["WARC/1.0", "WARC/1.1"];
+
+/// The status written to the index for resource and metadata
+/// records, which have no HTTP response of their own.
+pub const RESOURCE_STATUS_CODE: usize = 200;
+
+/// The status written to the index for server-not-modified
+/// revisits which have no HTTP response headers.
+pub const NOT_MODIFIED_STATUS_CODE: usize = 304;
+
+/// The mime type written to the index for revisit records,
+/// as expected by pywb and `ReplayWeb`.
+pub const REVISIT_MIME_TYPE: &str = "warc/revisit";
+
+/// The most text kept for each page by default, when text is
+/// extracted. This keeps `pages.jsonl` to a manageable size.
+pub const DEFAULT_MAX_TEXT_SIZE: usize = 65_536;
+
+/// How much memory the CDXJ index can take up while it's being
+/// sorted by default, before temporary files are used, in bytes.
+pub const DEFAULT_MAX_SORT_MEMORY: usize = 268_435_456;
+
+/// The number of CDXJ lines in each block of a `ZipNum` index by
+/// default, which is the same as pywb.
+pub const DEFAULT_ZIPNUM_BLOCK_SIZE: usize = 3000;
+
+/// Every gzip member starts with these bytes: the two byte
+/// gzip ID, followed by the deflate compression method.
+const GZIP_MAGIC_BYTES: [u8; 3] = [0x1f, 0x8b, 0x08];
+
+/// Every zstd frame starts with these bytes.
+const ZSTD_MAGIC_BYTES: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// A zstd WARC starts with a skippable frame which holds the
+/// dictionary, marked by these bytes. See the specification at
+/// <https://iipc.github.io/warc-specifications/specifications/warc-zstd/>
+const ZSTD_DICTIONARY_MAGIC_BYTES: [u8; 4] = [0x5d, 0x2a, 0x4d, 0x18];
+
+/// # Index a WARC file
+///
+/// Reads through every record in a WARC file and returns
+/// the records which should be written to the CDXJ index.
+///
+/// # Errors
+///
+/// Returns an [`IndexerError`] if the file cannot be read, or
+/// if any record in it is malformed. The error includes the file
+/// name and byte offset of the record where reading stopped.
+pub fn indexer(warc_file_path: &Path) -> Result<Vec<IndexRecord>, IndexerError> {
+    let mut warc_reader = WarcReader::new(warc_file_path, IndexerOptions::default())?;
+    return collect_index(&mut warc_reader);
+}
+
+/// # Index a WARC file, skipping over corrupt records
+///
+/// Works like [`indexer`], but instead of stopping at the first record
+/// which can't be read, skips ahead to the next WARC header (or the
+/// next gzip member in a compressed WARC) and carries on. Returns
+/// the index along with a list of the regions which were skipped.
+///
+/// # Errors
+///
+/// Returns an [`IndexerError`] if the file cannot be opened.
+pub fn lenient_indexer(
+    warc_file_path: &Path,
+) -> Result<(Vec<IndexRecord>, Vec<SkippedRegion>), IndexerError> {
+    let options = IndexerOptions {
+        lenient: true,
+        ..Default::default()
+    };
+    return indexer_with_options(warc_file_path, &options);
+}
+
+/// Options for [`indexer_with_options`].
+#[derive(Debug, Clone)]
+pub struct IndexerOptions {
+    /// Skip over corrupt records, see [`lenient_indexer`].
+    pub lenient: bool,
+    /// Read the title of HTML pages, for `pages.jsonl`. This means
+    /// reading the payload of each page rather than skipping over
+    /// it, so it's left off by default.
+    pub extract_titles: bool,
+    /// Extract the visible text of HTML pages, for full-text search.
+    /// The title is read along with it.
+    pub extract_text: bool,
+    /// The most text kept for each page, in bytes, which
+    /// defaults to [`DEFAULT_MAX_TEXT_SIZE`].
+    pub max_text_size: usize,
+    /// Only the records this picks out as pages have their title and
+    /// text read. The default is [`HtmlPages`]. Responses haven't been
+    /// paired with their request at this point, so they all look like
+    /// GET requests.
+    pub page_selector: Arc<dyn PageSelector>,
+}
+impl Default for IndexerOptions {
+    fn default() -> Self {
+        return Self {
+            lenient: false,
+            extract_titles: false,
+            extract_text: false,
+            max_text_size: DEFAULT_MAX_TEXT_SIZE,
+            page_selector: Arc::new(HtmlPages),
+        };
+    }
+}
+
+/// # Index a WARC file, with options
+///
+/// Works like [`indexer`], with [`IndexerOptions`] to change what's read
+/// from the file. Returns the index along with a list of the regions
+/// which were skipped, which is always empty unless `lenient` is set.
+///
+/// # Errors
+///
+/// Returns an [`IndexerError`], see [`indexer`] and [`lenient_indexer`].
+pub fn indexer_with_options(
+    warc_file_path: &Path,
+    options: &IndexerOptions,
+) -> Result<(Vec<IndexRecord>, Vec<SkippedRegion>), IndexerError> {
+    let mut warc_reader = WarcReader::new(warc_file_path, options.clone())?;
+    let index = collect_index(&mut warc_reader)?;
+    return Ok((index, warc_reader.skipped_regions));
+}
+
+fn collect_index(warc_reader: &mut WarcReader) -> Result<Vec<IndexRecord>, IndexerError> {
     let mut index = Vec::with_capacity(512);
+    let mut requests = Vec::new();
 
-    for index_record in WarcReader::new(warc_file_path) {
-        if index_record.record_type.is_some()
-            && !index_record.mime_type.is_empty()
-            && index_record.http_status_code != 0
-        {
+    for index_record in warc_reader {
+        let index_record = index_record?;
+        if index_record.record_type == Some(WarcRecordType::Request) {
+            requests.push(PairedRequest::from(index_record));
+        } else if index_record.is_indexable() {
             index.push(index_record);
         }
     }
-    return index;
+
+    pair_requests(&mut index, &requests);
+    return Ok(index);
 }
 
+/// The parts of a request record which are copied to its response.
+/// Only these are kept, so that the rest of each request can be
+/// dropped as soon as it's read.
+struct PairedRequest {
+    record_id: String,
+    concurrent_to: String,
+    http_method: Option<String>,
+    request_body: Option<String>,
+}
+impl From<IndexRecord> for PairedRequest {
+    fn from(request: IndexRecord) -> Self {
+        return Self {
+            record_id: request.record_id,
+            concurrent_to: request.concurrent_to,
+            http_method: request.http_method,
+            request_body: request.request_body,
+        };
+    }
+}
+
+/// Copy the HTTP method and request body from each request record to
+/// the response it is paired with. Records are linked by the
+/// WARC-Concurrent-To header, which can be on either one of the pair.
+fn pair_requests(index: &mut [IndexRecord], requests: &[PairedRequest]) {
+    let mut requests_by_id: HashMap<&str, &PairedRequest> = HashMap::with_capacity(requests.len());
+    let mut requests_by_concurrent_id: HashMap<&str, &PairedRequest> =
+        HashMap::with_capacity(requests.len());
+    for request in requests {
+        requests_by_id.insert(&request.record_id, request);
+        if !request.concurrent_to.is_empty() {
+            requests_by_concurrent_id.insert(&request.concurrent_to, request);
+        }
+    }
+
+    for record in index {
+        let Some(request) = requests_by_concurrent_id
+            .get(record.record_id.as_str())
+            .or_else(|| return requests_by_id.get(record.concurrent_to.as_str()))
+        else {
+            continue;
+        };
+
+        record.http_method.clone_from(&request.http_method);
+        record.request_body.clone_from(&request.request_body);
+    }
+}
+
+/// # Write the CDXJ index as a string
+///
+/// Lines are sorted by urlkey and then timestamp, as tools which read
+/// CDXJ expect. Everything is sorted in memory, see [`write_cdxj`] to
+/// sort larger indexes using temporary files.
+///
+/// URLs which can't be turned into a SURT, see [`create_surt`], are
+/// used as they are for the urlkey, with whitespace escaped.
+///
+/// # Panics
+///
+/// Will panic if the record timestamp is not valid RFC 3339.
 #[must_use]
 pub fn to_cdxj_string(index: &[IndexRecord]) -> String {
-    let mut cdxj_index = String::with_capacity(512);
+    let mut cdxj_lines: Vec<String> = index.iter().map(to_cdxj_line).collect();
+    cdxj_lines.sort_unstable();
+    return cdxj_lines.join("\n");
+}
 
-    for record in index {
-        let surt = create_surt(&record.url).unwrap();
-        // Parse the timestamp, and write out a formatted string
-        let timestamp = DateTime::parse_from_rfc3339(&record.timestamp).unwrap();
-        let formatted_record = format!(
-            "{} {} {{\"url\":\"{}\",\"digest\":\"{}\",\"mime\":\"{}\",\"offset\":{},\"length\":{},\"status\":{},\"filename\":\"{}\"}}\n",
-            surt,
-            timestamp.format("%Y%m%d%H%M%S"),
-            record.url,
-            record.digest,
-            record.mime_type,
-            record.offset,
-            record.content_length,
-            record.http_status_code,
-            record.file_name
+/// # Write the CDXJ index
+///
+/// Works like [`to_cdxj_string`], but writes the index out as it's
+/// sorted. Once the lines take up more than `max_sort_memory` bytes,
+/// they're sorted in batches which are written to temporary files,
+/// then merged together.
+///
+/// # Errors
+///
+/// Returns an error if a temporary file can't be written or
+/// read, or if writing to `writer` fails.
+///
+/// # Panics
+///
+/// See [`to_cdxj_string`].
+pub fn write_cdxj<W: Write>(
+    index: &[IndexRecord],
+    writer: &mut W,
+    max_sort_memory: usize,
+) -> io::Result<()> {
+    return sort::write_sorted_lines(index.iter().map(to_cdxj_line), writer, max_sort_memory);
+}
+
+/// # Write a `ZipNum` index
+///
+/// Splits a sorted CDXJ index, such as from [`write_cdxj`], into blocks
+/// of `block_size` lines which are gzipped separately, and writes a
+/// secondary index saying where each block starts. This lets replay
+/// tools load only the parts of a large index which they need.
+///
+/// # Errors
+///
+/// Returns an error if a block can't be compressed.
+pub fn to_zipnum_index(cdxj_index: &[u8], block_size: usize) -> io::Result<ZipNumIndex> {
+    return zipnum::write_zipnum(cdxj_index, block_size);
+}
+
+/// Format a single record as a line of the CDXJ index.
+fn to_cdxj_line(record: &IndexRecord) -> String {
+    // The JSON block is built up as a map and written out by
+    // serde_json, so that every value is quoted and escaped.
+    let mut json_block = Map::with_capacity(12);
+
+    // Captures made with a method other than GET have the method and
+    // request body added to the URL before it is turned into a SURT,
+    // so that they don't collide with GET captures of the same URL.
+    let surt = match &record.http_method {
+        Some(method) if !record.is_get_request() => {
+            let request_body = record.request_body.as_deref();
+            let method_query = request::method_query(method, request_body);
+            let url = request::append_query(&record.url, &method_query);
+            create_surt(&url).unwrap_or_else(|_| return escape_urlkey(&url))
+        }
+        // URLs which can't be turned into a SURT, like `mailto:`
+        // URLs, are used as they are, apart from any whitespace.
+        _ => create_surt(&record.url).unwrap_or_else(|_| return escape_urlkey(&record.url)),
+    };
+
+    // Revisits are given their own mime type.
+    let mime_type = if record.record_type == Some(WarcRecordType::Revisit) {
+        REVISIT_MIME_TYPE
+    } else {
+        record.mime_type.essence()
+    };
+    json_block.insert("url".to_owned(), Value::from(record.url.as_str()));
+    json_block.insert("digest".to_owned(), Value::from(record.digest.as_str()));
+    json_block.insert("mime".to_owned(), Value::from(mime_type));
+    json_block.insert("offset".to_owned(), Value::from(record.offset));
+    json_block.insert("length".to_owned(), Value::from(record.content_length));
+    json_block.insert("status".to_owned(), Value::from(record.http_status_code));
+    json_block.insert("filename".to_owned(), Value::from(&*record.file_name));
+
+    // Revisits point to the original capture,
+    // so that its payload can be found.
+    if record.record_type == Some(WarcRecordType::Revisit) {
+        if let Some(refers_to_url) = &record.refers_to_url {
+            json_block.insert(
+                "refersToUrl".to_owned(),
+                Value::from(refers_to_url.as_str()),
+            );
+        }
+        if let Some(refers_to_date) = record
+            .refers_to_date
+            .as_ref()
+            .and_then(|refers_to_date| return DateTime::parse_from_rfc3339(refers_to_date).ok())
+        {
+            json_block.insert(
+                "refersToDate".to_owned(),
+                Value::from(refers_to_date.format("%Y%m%d%H%M%S").to_string()),
+            );
+        }
+    }
+    if let Some(method) = record
+        .http_method
+        .as_deref()
+        .filter(|_| return !record.is_get_request())
+    {
+        json_block.insert("method".to_owned(), Value::from(method));
+        json_block.insert(
+            "requestBody".to_owned(),
+            Value::from(record.request_body.as_deref().unwrap_or_default()),
         );
-        cdxj_index.push_str(&formatted_record);
     }
-    return cdxj_index.trim_end().to_owned();
+
+    // Parse the timestamp, and write out a formatted string
+    let timestamp = DateTime::parse_from_rfc3339(&record.timestamp).unwrap();
+    return format!(
+        "{} {} {}",
+        surt,
+        timestamp.format("%Y%m%d%H%M%S"),
+        Value::Object(json_block)
+    );
 }
 
+/// List the pages in the index, chosen by the default [`HtmlPages`]
+/// selector. See [`to_pages_json_string_with_selector`].
 #[must_use]
 pub fn to_pages_json_string(index: &[IndexRecord]) -> String {
-    let mut pages_index =
-        "{\"format\":\"json-pages-1.0\",\"id\":\"pages\",\"title\":\"All Pages\"}\n".to_owned();
-
-    for record in index.iter().enumerate() {
-        let record_struct = record.1;
-        let record_number = record.0;
-        if record_struct.is_page {
-            let formatted_record = format!(
-                "{{\"id\":\"{}\",\"url\":\"{}\",\"ts\":\"{}\"}}\n",
-                record_number, record_struct.url, record_struct.timestamp,
-            );
-            pages_index.push_str(&formatted_record);
+    return to_pages_json_string_with_selector(index, &HtmlPages);
+}
+
+/// List the records in the index which the [`PageSelector`] picks
+/// out as pages, in the `json-pages-1.0` format.
+#[must_use]
+pub fn to_pages_json_string_with_selector(
+    index: &[IndexRecord],
+    page_selector: &dyn PageSelector,
+) -> String {
+    return pages_json_string(
+        "{\"format\":\"json-pages-1.0\",\"id\":\"pages\",\"title\":\"All Pages\"}",
+        index
+            .iter()
+            .filter(|record| return record.is_selected_page(page_selector)),
+    );
+}
+
+/// Divide the pages which the [`PageSelector`] picks out between
+/// seeds and everything else, returning the contents of
+/// `pages/pages.jsonl` and `pages/extraPages.jsonl` in that order.
+#[must_use]
+pub fn to_seed_pages_json_strings(
+    index: &[IndexRecord],
+    page_selector: &dyn PageSelector,
+    seed_urls: &SeedUrls,
+) -> (String, String) {
+    let (seed_pages, extra_pages): (Vec<&IndexRecord>, Vec<&IndexRecord>) = index
+        .iter()
+        .filter(|record| return record.is_selected_page(page_selector))
+        .partition(|record| return seed_urls.contains(&record.url));
+
+    return (
+        pages_json_string(
+            "{\"format\":\"json-pages-1.0\",\"id\":\"pages\",\"title\":\"Seed Pages\"}",
+            seed_pages.into_iter(),
+        ),
+        pages_json_string(
+            "{\"format\":\"json-pages-1.0\",\"id\":\"extra-pages\",\"title\":\"Extra Pages\"}",
+            extra_pages.into_iter(),
+        ),
+    );
+}
+
+/// Write out a pages file, with a header line then one line per page.
+fn pages_json_string<'a>(header: &str, pages: impl Iterator<Item = &'a IndexRecord>) -> String {
+    let mut pages_index = format!("{header}\n");
+
+    for record_struct in pages {
+        // Titles and text come from the page itself, so could hold
+        // anything. Every value is quoted and escaped by serde_json.
+        let mut page = Map::with_capacity(5);
+        page.insert("id".to_owned(), Value::from(record_struct.page_id()));
+        page.insert("url".to_owned(), Value::from(record_struct.url.as_str()));
+        page.insert(
+            "ts".to_owned(),
+            Value::from(record_struct.timestamp.as_str()),
+        );
+        if let Some(title) = &record_struct.title {
+            page.insert("title".to_owned(), Value::from(title.as_str()));
         }
+        if let Some(text) = &record_struct.text {
+            page.insert("text".to_owned(), Value::from(text.as_str()));
+        }
+        pages_index.push_str(&Value::Object(page).to_string());
+        pages_index.push('\n');
     }
     return pages_index.trim_end().to_owned();
 }
@@ -76,6 +434,15 @@ enum WarcRecordType {
     Revisit,
     Resource,
     Metadata,
+    Request,
+}
+/// The WARC-Profile of a revisit record.
+#[derive(Debug, PartialEq, Clone)]
+enum RevisitProfile {
+    /// The payload was the same as an earlier capture.
+    IdenticalPayloadDigest,
+    /// The server said the resource hadn't changed, usually with a 304 status.
+    ServerNotModified,
 }
 #[derive(Debug, Clone)]
 pub struct IndexRecord {
@@ -86,187 +453,773 @@ pub struct IndexRecord {
     timestamp: String,
     record_type: Option<WarcRecordType>,
     url: String,
-    is_page: bool,
     is_http: bool,
     http_status_code: usize,
-    mime_type: String,
-    file_name: String,
+    mime_type: MimeType,
+    /// Shared between every record from the same file.
+    file_name: Arc<str>,
+    record_id: String,
+    concurrent_to: String,
+    /// The HTTP method of the request, for responses this
+    /// is taken from the request record they are paired with.
+    http_method: Option<String>,
+    /// The request body canonicalised into a query string,
+    /// see [`request::canonicalise_body`].
+    request_body: Option<String>,
+    revisit_profile: Option<RevisitProfile>,
+    /// For revisits, the URL and date of the original capture.
+    refers_to_url: Option<String>,
+    refers_to_date: Option<String>,
+    /// The HTTP response headers. These aren't kept for requests.
+    http_headers: HeaderMap,
+    /// The title of an HTML page, see [`html::read_title`].
+    title: Option<String>,
+    /// The visible text of an HTML page, if it was
+    /// asked for, see [`html::extract_text`].
+    text: Option<String>,
 }
 impl IndexRecord {
-    fn new() -> Self {
+    const fn new(offset: usize, file_name: Arc<str>) -> Self {
         return Self {
-            offset: 0,
+            offset,
             content_length: 0,
             header_length: 0,
-            digest: String::with_capacity(128),
-            timestamp: String::with_capacity(36),
+            digest: String::new(),
+            timestamp: String::new(),
             record_type: None,
-            url: String::with_capacity(128),
-            is_page: false,
+            url: String::new(),
             is_http: false,
             http_status_code: 0,
-            mime_type: String::with_capacity(36),
-            file_name: String::with_capacity(36),
+            mime_type: MimeType::new(),
+            file_name,
+            record_id: String::new(),
+            concurrent_to: String::new(),
+            http_method: None,
+            request_body: None,
+            revisit_profile: None,
+            refers_to_url: None,
+            refers_to_date: None,
+            http_headers: HeaderMap::new(),
+            title: None,
+            text: None,
+        };
+    }
+}
+
+impl IndexRecord {
+    /// The HTTP headers of the record, which is empty
+    /// for records which don't hold an HTTP message.
+    #[must_use]
+    pub const fn http_headers(&self) -> &HeaderMap {
+        return &self.http_headers;
+    }
+
+    /// The Content-Type of the record. For records holding an HTTP message
+    /// this is from the HTTP headers, otherwise it's from the WARC headers.
+    #[must_use]
+    pub const fn mime_type(&self) -> &MimeType {
+        return &self.mime_type;
+    }
+
+    /// The URL which was captured, from the WARC-Target-URI header.
+    #[must_use]
+    pub fn url(&self) -> &str {
+        return &self.url;
+    }
+
+    /// The capture date, from the WARC-Date header, in RFC 3339 format.
+    #[must_use]
+    pub fn timestamp(&self) -> &str {
+        return &self.timestamp;
+    }
+
+    /// The HTTP status code. Resource and metadata records, and
+    /// revisits without HTTP headers, are given one, see
+    /// [`RESOURCE_STATUS_CODE`] and [`NOT_MODIFIED_STATUS_CODE`].
+    #[must_use]
+    pub const fn http_status_code(&self) -> usize {
+        return self.http_status_code;
+    }
+
+    /// The title of an HTML page, if it has one.
+    #[must_use]
+    pub fn title(&self) -> Option<&str> {
+        return self.title.as_deref();
+    }
+
+    /// The visible text of an HTML page, if text was extracted, see
+    /// [`IndexerOptions::extract_text`].
+    #[must_use]
+    pub fn text(&self) -> Option<&str> {
+        return self.text.as_deref();
+    }
+
+    /// An id for the record in `pages.jsonl`, which is a version 5 UUID
+    /// made from the WARC-Record-ID. The same record always gets the same
+    /// id, so ids don't change when a WACZ is rebuilt, and records from
+    /// different collections don't share one. Records without an id
+    /// fall back on the URL and timestamp.
+    #[must_use]
+    pub fn page_id(&self) -> String {
+        let name = if self.record_id.is_empty() {
+            format!("{} {}", self.url, self.timestamp).into_bytes()
+        } else {
+            strip_angle_brackets(self.record_id.as_bytes()).to_vec()
         };
+        return Uuid::new_v5(&Uuid::NAMESPACE_URL, &name).to_string();
+    }
+
+    /// The HTTP method, if there was a request record to say what it was.
+    #[must_use]
+    pub fn http_method(&self) -> Option<&str> {
+        return self.http_method.as_deref();
     }
+
+    /// Whether the record was captured with a GET request, or
+    /// has no request record to say otherwise.
+    #[must_use]
+    pub fn is_get_request(&self) -> bool {
+        return self
+            .http_method
+            .as_ref()
+            .is_none_or(|method| return method == "GET");
+    }
+
+    /// Whether the [`PageSelector`] picks out the record as a page. Revisits
+    /// are never pages, as they have no title or text of their own and the
+    /// original capture would already be listed under the same URL.
+    pub(crate) fn is_selected_page(&self, page_selector: &dyn PageSelector) -> bool {
+        return self.record_type != Some(WarcRecordType::Revisit) && page_selector.is_page(self);
+    }
+
+    /// Whether the record should be written to the index. Revisits
+    /// often have no HTTP headers, so don't need a mime type.
+    fn is_indexable(&self) -> bool {
+        return match self.record_type {
+            Some(WarcRecordType::Revisit) => self.http_status_code != 0,
+            // Crawlers write metadata about an HTTP capture, such as its
+            // outlinks, under the capture's own URL and date. Indexing it
+            // would put it alongside the response, and replay could pick
+            // it up in place of the page.
+            Some(WarcRecordType::Metadata)
+                if self.url.starts_with("http://") || self.url.starts_with("https://") =>
+            {
+                false
+            }
+            Some(_) => !self.mime_type.essence().is_empty() && self.http_status_code != 0,
+            None => false,
+        };
+    }
+
+    /// Whether the record block starts with HTTP headers,
+    /// which we want to read as well as the WARC headers.
+    fn has_http_headers(&self) -> bool {
+        return [
+            Some(WarcRecordType::Response),
+            Some(WarcRecordType::Revisit),
+            Some(WarcRecordType::Request),
+        ]
+        .contains(&self.record_type)
+            && self.is_http
+            && self.content_length > 0;
+    }
+
+    /// Whether the record block holds a successfully captured
+    /// HTML page, which we want to read the title of.
+    fn has_html_payload(&self) -> bool {
+        return [
+            Some(WarcRecordType::Response),
+            Some(WarcRecordType::Resource),
+        ]
+        .contains(&self.record_type)
+            && matches!(
+                self.mime_type.essence(),
+                "text/html" | "application/xhtml+xml"
+            )
+            && (200..=299).contains(&self.http_status_code)
+            && self.content_length > 0;
+    }
+}
+
+/// How the records in a WARC file are compressed.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Compression {
+    None,
+    /// Each record is a separate gzip member.
+    Gzip,
+    /// Each record is a separate zstd frame, which
+    /// may be compressed using a shared dictionary.
+    Zstd,
 }
 
 struct WarcReader {
     reader: BufReader<File>,
     file_offset: usize,
     file_size: usize,
-    file_name: String,
-    is_gzip: bool,
+    file_name: Arc<str>,
+    compression: Compression,
+    /// The dictionary used to decompress a zstd WARC, if it has one.
+    zstd_dictionary: Option<DecoderDictionary<'static>>,
+    /// Whether the reader is already at `file_offset`, which it is
+    /// after reading a record, so that it doesn't need to seek.
+    reader_at_offset: bool,
+    /// Header blocks are read into this, so that the
+    /// same buffer can be used for every record.
+    header_buffer: Vec<u8>,
+    /// Whether to skip over records which can't be read,
+    /// rather than stopping at the first one, and what to
+    /// read from pages.
+    options: IndexerOptions,
+    skipped_regions: Vec<SkippedRegion>,
 }
 impl WarcReader {
-    fn new(warc_file_path: &Path) -> Self {
-        let file = File::open(warc_file_path).unwrap();
-        let file_size = usize::try_from(file.metadata().unwrap().len()).unwrap();
-
-        // Check whether the warc is gzipped
-        let is_gzip = warc_file_path
-            .extension()
-            .is_some_and(|extension| return extension == "gz");
-
+    fn new(warc_file_path: &Path, options: IndexerOptions) -> Result<Self, IndexerError> {
         // Define the filename, to pass into each record.
         let file_name = warc_file_path
             .file_name()
-            .unwrap()
-            .to_os_string()
-            .into_string()
-            .unwrap();
+            .map(|file_name| return file_name.to_string_lossy().into_owned())
+            .unwrap_or_default();
 
-        return Self {
-            reader: BufReader::new(file),
-            file_offset: 0,
+        let file = match File::open(warc_file_path) {
+            Ok(file) => file,
+            Err(error) => return Err(IndexerError::FileReadError { file_name, error }),
+        };
+        let file_size = match file.metadata() {
+            Ok(metadata) => to_usize(metadata.len()),
+            Err(error) => return Err(IndexerError::FileReadError { file_name, error }),
+        };
+
+        // Check whether the warc is gzipped by looking at the start of the
+        // file. Files are often renamed, so the extension is only used if
+        // the file is damaged at the start and we're in lenient mode.
+        let mut reader = BufReader::new(file);
+        let compression = match detect_compression(&mut reader) {
+            Ok(Some(compression)) => compression,
+            Ok(None) if options.lenient || file_size == 0 => {
+                match warc_file_path
+                    .extension()
+                    .and_then(|extension| return extension.to_str())
+                {
+                    Some("gz") => Compression::Gzip,
+                    Some("zst") => Compression::Zstd,
+                    _ => Compression::None,
+                }
+            }
+            Ok(None) => return Err(IndexerError::UnrecognisedFormatError { file_name }),
+            Err(error) => return Err(IndexerError::FileReadError { file_name, error }),
+        };
+
+        // The records in a zstd WARC start after the dictionary.
+        let (zstd_dictionary, file_offset) = if compression == Compression::Zstd {
+            match read_zstd_dictionary(&mut reader) {
+                Ok(dictionary) => dictionary,
+                Err(error) => return Err(IndexerError::FileReadError { file_name, error }),
+            }
+        } else {
+            (Vec::new(), 0)
+        };
+        // Prepare the dictionary once, rather than for every record.
+        let zstd_dictionary =
+            (!zstd_dictionary.is_empty()).then(|| return DecoderDictionary::copy(&zstd_dictionary));
+
+        return Ok(Self {
+            reader,
+            file_offset,
             file_size,
-            file_name,
-            is_gzip,
+            file_name: Arc::from(file_name),
+            compression,
+            zstd_dictionary,
+            reader_at_offset: true,
+            header_buffer: Vec::with_capacity(4096),
+            options,
+            skipped_regions: Vec::new(),
+        });
+    }
+
+    /// Read the record starting at the current file offset, and move
+    /// the offset on to the start of the next record.
+    fn read_record(&mut self) -> Result<Option<IndexRecord>, RecordError> {
+        let parsed_record = IndexRecord::new(self.file_offset, Arc::clone(&self.file_name));
+
+        // After reading a record the reader is already at the start of the
+        // next one, so only seek if something else has moved it since.
+        if !self.reader_at_offset {
+            self.reader
+                .seek(SeekFrom::Start(self.file_offset as u64))
+                .map_err(RecordError::ReadError)?;
+        }
+        self.reader_at_offset = false;
+
+        if matches!(self.compression, Compression::Gzip | Compression::Zstd) {
+            // Wrap the reader in a decoder for a single gzip member or zstd
+            // frame. Only the headers are kept, the rest of the record is
+            // decompressed and thrown away as it's read.
+            let streamed_record = if self.compression == Compression::Gzip {
+                let decoder = GzDecoder::new(&mut self.reader);
+                stream_record(
+                    parsed_record,
+                    decoder,
+                    &mut self.header_buffer,
+                    &self.options,
+                )
+            } else {
+                let decoder = match &self.zstd_dictionary {
+                    Some(dictionary) => {
+                        ZstdDecoder::with_prepared_dictionary(&mut self.reader, dictionary)
+                    }
+                    None => ZstdDecoder::with_buffer(&mut self.reader),
+                }
+                .map_err(RecordError::ReadError)?
+                .single_frame();
+                stream_record(
+                    parsed_record,
+                    decoder,
+                    &mut self.header_buffer,
+                    &self.options,
+                )
+            }
+            .map_err(RecordError::ReadError)?;
+
+            // The reader is now at the end of the gzip member or zstd
+            // frame, which is the start of the next record in the file.
+            let file_position = self
+                .reader
+                .stream_position()
+                .map_err(RecordError::ReadError)?;
+            self.file_offset = to_usize(file_position);
+            self.reader_at_offset = true;
+
+            return streamed_record;
+        }
+
+        let Some(parsed_record) =
+            read_warc_header(parsed_record, &mut self.reader, &mut self.header_buffer)?
+        else {
+            return Ok(None);
+        };
+
+        // Now that we've parsed the header, add the header length
+        // and content length to the file offset. Also add 4 bytes
+        // to account for the newlines separating each record. The
+        // new file offset should now be at the start of the next record.
+        let content_length = parsed_record.content_length;
+        // A huge Content-Length would overflow, rather than just
+        // running past the end of the file.
+        let Some((block_end, next_offset)) = (self.file_offset + parsed_record.header_length)
+            .checked_add(content_length)
+            .and_then(|block_end| return Some((block_end, block_end.checked_add(4)?)))
+        else {
+            return Err(RecordError::ContentLengthOverflow {
+                offset: self.file_offset,
+                content_length,
+            });
+        };
+
+        // Read the HTTP headers while we're at the start of the block, but
+        // hold on to any error until we know the record is framed correctly.
+        let mut block_reader = (&mut self.reader).take(content_length as u64);
+        let parsed_record = read_block(
+            parsed_record,
+            &mut block_reader,
+            &mut self.header_buffer,
+            &self.options,
+        );
+        let unread_length = block_reader.limit();
+
+        // If the content length is wrong then we won't land on the next
+        // record, so check for this before moving the offset on.
+        if !self.has_record_trailer(unread_length, block_end)? {
+            return Err(RecordError::InvalidRecordLength(content_length));
+        }
+        self.file_offset = next_offset.min(self.file_size);
+        self.reader_at_offset = true;
+
+        return parsed_record.map(Some);
+    }
+
+    /// Skip over the rest of a record block in an uncompressed WARC, and
+    /// check that it's followed by the two newlines which separate records.
+    fn has_record_trailer(
+        &mut self,
+        unread_length: u64,
+        block_end: usize,
+    ) -> Result<bool, RecordError> {
+        // A block which runs past the end of the file means the length was wrong.
+        if block_end > self.file_size {
+            return Ok(false);
+        }
+        let skip_length = i64::try_from(unread_length).unwrap_or(i64::MAX);
+        self.reader
+            .seek_relative(skip_length)
+            .map_err(RecordError::ReadError)?;
+
+        // Many writers leave off or cut short the trailer of the last
+        // record, so the end of the file can stand in for it. A trailer
+        // with another record after it still has to be complete.
+        let trailer_length = (self.file_size - block_end).min(4);
+        let mut trailer = [0; 4];
+        self.reader
+            .read_exact(&mut trailer[..trailer_length])
+            .map_err(RecordError::ReadError)?;
+        if trailer_length < 4 {
+            return Ok(trailer[..trailer_length]
+                .iter()
+                .all(|byte| return byte.is_ascii_whitespace()));
+        }
+        return Ok(&trailer == b"\r\n\r\n");
+    }
+
+    /// Find the start of the next record at or after an offset, by looking
+    /// for the next gzip member, or the next "WARC/" at the start of a line.
+    fn find_next_record(&mut self, offset: usize) -> io::Result<Option<usize>> {
+        // Uncompressed records are matched with the preceding newline, so
+        // start one byte early in case the record is right at the offset.
+        let (pattern, search_offset): (&[u8], usize) = match self.compression {
+            Compression::Gzip => (&GZIP_MAGIC_BYTES, offset),
+            Compression::Zstd => (&ZSTD_MAGIC_BYTES, offset),
+            Compression::None => (b"\nWARC/", offset - 1),
         };
+
+        self.reader.seek(SeekFrom::Start(search_offset as u64))?;
+
+        // Read through the file in chunks, keeping the end of the previous
+        // chunk in case the pattern is split across two of them.
+        let mut window: Vec<u8> = Vec::with_capacity(8192 + pattern.len());
+        let mut window_offset = search_offset;
+        let mut chunk = [0; 8192];
+        loop {
+            let bytes_read = self.reader.read(&mut chunk)?;
+            if bytes_read == 0 {
+                return Ok(None);
+            }
+            window.extend_from_slice(&chunk[..bytes_read]);
+
+            if let Some(position) = window
+                .windows(pattern.len())
+                .position(|candidate| return candidate == pattern)
+            {
+                let record_offset = window_offset + position;
+                match self.compression {
+                    Compression::Gzip | Compression::Zstd => return Ok(Some(record_offset)),
+                    // Skip over the newline
+                    Compression::None => return Ok(Some(record_offset + 1)),
+                }
+            }
+
+            let drained = window.len().saturating_sub(pattern.len() - 1);
+            window.drain(..drained);
+            window_offset += drained;
+        }
+    }
+
+    /// In lenient mode, record a region of the file which could not be
+    /// indexed and move the offset on to the next record we can find.
+    fn skip_region(&mut self, record_offset: usize, reason: RecordError) {
+        // If the offset has already moved on, then the record was framed
+        // correctly and we know where the next one starts. Otherwise, look
+        // for the next record.
+        if self.file_offset <= record_offset {
+            self.file_offset = match self.find_next_record(record_offset + 1) {
+                Ok(Some(next_offset)) => next_offset,
+                Ok(None) | Err(_) => self.file_size,
+            };
+        }
+        let length = self.file_offset - record_offset;
+
+        // Join up with the previous region if there was nothing
+        // readable between the two.
+        if let Some(previous_region) = self.skipped_regions.last_mut() {
+            if previous_region.offset + previous_region.length == record_offset {
+                previous_region.length += length;
+                return;
+            }
+        }
+        self.skipped_regions.push(SkippedRegion {
+            file_name: self.file_name.to_string(),
+            offset: record_offset,
+            length,
+            reason,
+        });
     }
 }
 impl Iterator for WarcReader {
-    type Item = IndexRecord;
+    type Item = Result<IndexRecord, IndexerError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut parsed_record = IndexRecord::new();
-
-        parsed_record.offset = self.file_offset;
-        self.file_name.clone_into(&mut parsed_record.file_name);
-
-        if self.file_size > self.file_offset {
-            // Seek to the byte offset and start reading
-            // from there onwards.
-            let reader = &mut self.reader;
-
-            // Start the reader from the file offset.
-            reader
-                .seek(SeekFrom::Start(self.file_offset.try_into().unwrap())) // convert usize to u64
-                .unwrap();
-            if self.is_gzip {
-                // Wrap the reader in a GzDecoder and instantiate
-                // an empty string to copy data into.
-                let mut decoder = GzDecoder::new(reader);
-                let mut byte_buffer = Vec::with_capacity(2048);
-
-                // Read bytes from the decoder to a byte vector.
-                decoder.read_to_end(&mut byte_buffer).unwrap();
-
-                // Find the position of the reader in the file after decompression.
-                let file_position =
-                    usize::try_from(decoder.get_mut().stream_position().unwrap()).unwrap();
-
-                // The number of bytes read will be the position of
-                // the reader in the file, minus the offset it read from.
-                let bytes_read = file_position - self.file_offset;
-
-                // Now add the bytes_read back to the offset
-                // for the next record in the file
-                self.file_offset += bytes_read;
-
-                // A byte slice has a Read trait, and can be passed into
-                // read_header_block().
-                let mut byte_reader = byte_buffer.as_slice();
-
-                let warc_header_buffer = read_header_block(&mut byte_reader)?;
-
-                // Set the header length
-                parsed_record.header_length = warc_header_buffer.len();
-
-                // First, check whether the first 8 bytes of the record
-                // match "WARC/1.1".
-                if warc_header_buffer.starts_with("WARC/1.1") {
-                    parsed_record = process_headers(parsed_record, &warc_header_buffer);
-
-                    // If both of these conditions are met,
-                    // the record contains an HTTP resource.
-                    if [
-                        Some(WarcRecordType::Response),
-                        Some(WarcRecordType::Revisit),
-                    ]
-                    .contains(&parsed_record.record_type)
-                        && parsed_record.is_http
-                    {
-                        let http_header_buffer = read_header_block(&mut byte_reader)?;
-                        parsed_record = process_headers(parsed_record, &http_header_buffer);
+        // If the byte offset is greater than the file size,
+        // we're at the end of the file, so return none
+        // and close the iterator.
+        while self.file_offset < self.file_size {
+            let record_offset = self.file_offset;
+            match self.read_record() {
+                Ok(Some(parsed_record)) => return Some(Ok(parsed_record)),
+                Ok(None) => {
+                    // There was only whitespace left to read. An empty gzip
+                    // member can be skipped over, but in an uncompressed
+                    // file this is the end.
+                    if self.compression == Compression::None {
+                        self.file_offset = self.file_size;
                     }
-                    return Some(parsed_record);
-                } else {
-                    // If the header does not start with "WARC/1.1"
-                    // then return none. This should be an error.
-                    return None;
                 }
-            } else {
-                // This could be broken into a separate parse_header function.
-
-                // Read through the WARC header and return a string
-                let warc_header_buffer = read_header_block(reader)?;
-
-                // Set the header length
-                parsed_record.header_length = warc_header_buffer.len();
-
-                // First, check whether the first 8 bytes of the record
-                // match "WARC/1.1".
-                if warc_header_buffer.starts_with("WARC/1.1") {
-                    parsed_record = process_headers(parsed_record, &warc_header_buffer);
-
-                    // Now that we've parsed the header, add the header length
-                    // and content length to the file offset. Also add 4 bytes
-                    // to account for the newlines separating each record. The
-                    // new file offset should now be at the start of the next record.
-                    self.file_offset +=
-                        parsed_record.header_length + parsed_record.content_length + 4;
-
-                    // If both of these conditions are met,
-                    // the record contains an HTTP resource.
-                    if [
-                        Some(WarcRecordType::Response),
-                        Some(WarcRecordType::Revisit),
-                    ]
-                    .contains(&parsed_record.record_type)
-                        && parsed_record.is_http
-                    {
-                        let http_header_buffer = read_header_block(reader)?;
-                        parsed_record = process_headers(parsed_record, &http_header_buffer);
-                    }
-
-                    return Some(parsed_record);
-                } else {
-                    // If the header does not start with "WARC/1.1"
-                    // then return none. This should be an error.
-                    return None;
+                Err(reason) if self.options.lenient => self.skip_region(record_offset, reason),
+                Err(reason) => {
+                    // There is no reliable way to find the next record
+                    // after an error, so stop reading the file here.
+                    self.file_offset = self.file_size;
+                    return Some(Err(IndexerError::RecordError {
+                        file_name: self.file_name.to_string(),
+                        offset: record_offset,
+                        reason,
+                    }));
                 }
             }
+        }
+        return None;
+    }
+}
+
+/// Work out whether a file is a gzipped, zstd or uncompressed WARC from its
+/// first few bytes, returning `None` if it doesn't look like a WARC at all.
+/// The reader is left at the start of the file.
+fn detect_compression(reader: &mut BufReader<File>) -> io::Result<Option<Compression>> {
+    let start_of_file = reader.fill_buf()?;
+
+    let compression = if start_of_file.starts_with(b"WARC/") {
+        Some(Compression::None)
+    } else if start_of_file.starts_with(&GZIP_MAGIC_BYTES) {
+        // Plenty of gzip files aren't WARCs, so check what's inside too.
+        let mut start_of_record = Vec::with_capacity(5);
+        let is_warc = GzDecoder::new(&mut *reader)
+            .take(5)
+            .read_to_end(&mut start_of_record)
+            .is_ok_and(|_| return start_of_record == b"WARC/");
+        is_warc.then_some(Compression::Gzip)
+    } else if start_of_file.starts_with(&ZSTD_DICTIONARY_MAGIC_BYTES) {
+        Some(Compression::Zstd)
+    } else if start_of_file.starts_with(&ZSTD_MAGIC_BYTES) {
+        // A zstd WARC doesn't have to have a dictionary.
+        let mut start_of_record = Vec::with_capacity(5);
+        let is_warc = ZstdDecoder::with_buffer(&mut *reader).is_ok_and(|decoder| {
+            return decoder
+                .take(5)
+                .read_to_end(&mut start_of_record)
+                .is_ok_and(|_| return start_of_record == b"WARC/");
+        });
+        is_warc.then_some(Compression::Zstd)
+    } else {
+        None
+    };
+
+    reader.seek(SeekFrom::Start(0))?;
+    return Ok(compression);
+}
+
+/// Read the dictionary from the skippable frame at the start of a zstd WARC,
+/// and return it with the offset of the first record. The dictionary may
+/// itself be zstd compressed. Files without a dictionary get an empty one.
+fn read_zstd_dictionary(reader: &mut BufReader<File>) -> io::Result<(Vec<u8>, usize)> {
+    let mut frame_header = [0; 8];
+    reader.read_exact(&mut frame_header)?;
+    if frame_header[..4] != ZSTD_DICTIONARY_MAGIC_BYTES {
+        reader.seek(SeekFrom::Start(0))?;
+        return Ok((Vec::new(), 0));
+    }
+
+    let frame_size = u32::from_le_bytes([
+        frame_header[4],
+        frame_header[5],
+        frame_header[6],
+        frame_header[7],
+    ]);
+    let mut dictionary = Vec::new();
+    reader
+        .by_ref()
+        .take(u64::from(frame_size))
+        .read_to_end(&mut dictionary)?;
+    if dictionary.len() != frame_size as usize {
+        return Err(io::Error::from(ErrorKind::UnexpectedEof));
+    }
+
+    if dictionary.starts_with(&ZSTD_MAGIC_BYTES) {
+        dictionary = zstd::decode_all(dictionary.as_slice())?;
+    }
+    return Ok((dictionary, frame_header.len() + frame_size as usize));
+}
+
+/// Read the WARC header block of a record and parse it, returning
+/// `None` if there's nothing left but whitespace.
+fn read_warc_header<R: BufRead>(
+    mut record: IndexRecord,
+    reader: &mut R,
+    header_buffer: &mut Vec<u8>,
+) -> Result<Option<IndexRecord>, RecordError> {
+    if !read_header_block(reader, header_buffer)? {
+        return Ok(None);
+    }
+
+    // Set the header length
+    record.header_length = header_buffer.len();
+
+    // First, check whether the first 8 bytes of the record
+    // match a WARC version we know how to read.
+    check_warc_version(header_buffer)?;
+    return process_headers(record, header_buffer).map(Some);
+}
+
+/// Parse the headers of a compressed record from a streaming decoder,
+/// then read through to the end of the record without keeping the
+/// payload, so that memory use doesn't depend on the record size.
+///
+/// If the decoder fails then we don't know where the record ends, so
+/// this is returned as the outer error. Problems with the record itself
+/// are returned inside, once the reader has reached the end of it.
+fn stream_record<R: io::Read>(
+    record: IndexRecord,
+    decoder: R,
+    header_buffer: &mut Vec<u8>,
+    options: &IndexerOptions,
+) -> io::Result<Result<Option<IndexRecord>, RecordError>> {
+    let mut decoder = BufReader::new(decoder);
+
+    let parsed_record = match read_warc_header(record, &mut decoder, header_buffer) {
+        Ok(Some(parsed_record)) => {
+            // Only read as far as the end of the record block, in
+            // case the HTTP headers are never terminated.
+            let mut block_reader = (&mut decoder).take(parsed_record.content_length as u64);
+            read_block(parsed_record, &mut block_reader, header_buffer, options).map(Some)
+        }
+        Err(RecordError::ReadError(error)) => return Err(error),
+        other_result => other_result,
+    };
+
+    io::copy(&mut decoder, &mut io::sink())?;
+    return Ok(parsed_record);
+}
+
+/// Convert a file position to `usize`. File offsets are stored as `usize`
+/// throughout the indexer, so this saturates on (very unusual) platforms
+/// where a `u64` position does not fit.
+fn to_usize(position: u64) -> usize {
+    return usize::try_from(position).unwrap_or(usize::MAX);
+}
+
+/// Read the parts of a record block which we need, which is the HTTP
+/// headers if it has them, and the title and text if it's an HTML page
+/// and they were asked for.
+fn read_block<R: BufRead>(
+    mut parsed_record: IndexRecord,
+    block_reader: &mut R,
+    header_buffer: &mut Vec<u8>,
+    options: &IndexerOptions,
+) -> Result<IndexRecord, RecordError> {
+    if parsed_record.has_http_headers() {
+        parsed_record = read_http_block(parsed_record, block_reader, header_buffer)?;
+    }
+    if (options.extract_titles || options.extract_text)
+        && parsed_record.has_html_payload()
+        && options.page_selector.is_page(&parsed_record)
+    {
+        if options.extract_text {
+            if let Some(page_html) =
+                html::read_html(&parsed_record, block_reader, html::MAX_TEXT_HTML_SIZE)
+            {
+                parsed_record.title = html::extract_title(&page_html);
+                parsed_record.text = html::extract_text(&page_html, options.max_text_size);
+            }
         } else {
-            // If the byte offset is greater than the file size,
-            // we're at the end of the file, so return none
-            // and close the iterator.
-            return None;
+            parsed_record.title = html::read_title(&parsed_record, block_reader);
         }
     }
+    return Ok(parsed_record);
+}
+
+/// Read the HTTP headers at the start of a record block, and for
+/// requests, canonicalise the body which follows them.
+fn read_http_block<R: BufRead>(
+    mut parsed_record: IndexRecord,
+    block_reader: &mut R,
+    header_buffer: &mut Vec<u8>,
+) -> Result<IndexRecord, RecordError> {
+    match read_header_block(block_reader, header_buffer) {
+        Ok(true) => {}
+        // The block reader stops at the end of the record, so headers
+        // with no body after them may run to the end without a blank
+        // line. Keep the ones we've got.
+        Err(RecordError::UnexpectedEof) if !header_buffer.is_empty() => {}
+        Ok(false) => return Err(RecordError::UnexpectedEof),
+        Err(error) => return Err(error),
+    }
+    parsed_record = process_headers(parsed_record, header_buffer)?;
+
+    if parsed_record.record_type == Some(WarcRecordType::Request) && !parsed_record.is_get_request()
+    {
+        // Bodies can be large file uploads, and pywb only looks at the
+        // start of them, so don't read any further than that.
+        let mut request_body = Vec::with_capacity(1024);
+        block_reader
+            .take(request::MAX_BODY_SIZE as u64)
+            .read_to_end(&mut request_body)
+            .map_err(RecordError::ReadError)?;
+        parsed_record.request_body = Some(request::canonicalise_body(
+            &parsed_record.mime_type,
+            &request_body,
+        ));
+    }
+    return Ok(parsed_record);
+}
+
+/// WARC/1.0 wraps URIs in angle brackets, as in
+/// `<http://example.com/>`, so strip these off.
+fn strip_angle_brackets(value: &[u8]) -> &[u8] {
+    return value
+        .strip_prefix(b"<")
+        .and_then(|uri| return uri.strip_suffix(b">"))
+        .unwrap_or(value);
+}
+
+/// Header values are kept as bytes until they're needed as text.
+/// They should be ASCII, but anything else is replaced rather
+/// than stopping the record from being indexed.
+fn to_text(value: &[u8]) -> String {
+    return String::from_utf8_lossy(value).into_owned();
+}
+
+/// Return a header value as a date string, if it's valid RFC 3339.
+fn to_date(value: &[u8]) -> Option<&str> {
+    return std::str::from_utf8(value)
+        .ok()
+        .filter(|date| return DateTime::parse_from_rfc3339(date).is_ok());
+}
+
+/// Split a header block into lines, without their line endings.
+fn header_lines(buffer: &[u8]) -> impl Iterator<Item = &[u8]> {
+    return buffer
+        .split(|byte| return *byte == b'\n')
+        .map(|line| return line.strip_suffix(b"\r").unwrap_or(line));
 }
 
-fn read_header_block<R: BufRead>(reader: &mut R) -> Option<String> {
+/// Check whether a header block starts with one of the
+/// [`SUPPORTED_WARC_VERSIONS`].
+fn check_warc_version(header_buffer: &[u8]) -> Result<(), RecordError> {
+    if SUPPORTED_WARC_VERSIONS
+        .iter()
+        .any(|version| return header_buffer.starts_with(version.as_bytes()))
+    {
+        return Ok(());
+    } else {
+        let first_line = header_lines(header_buffer).next().unwrap_or_default();
+        return Err(RecordError::UnsupportedVersion(to_text(first_line)));
+    }
+}
+
+/// Read a header block, up to and including the blank line at the end
+/// of it, into a buffer. Returns `false` if there was nothing left to
+/// read but whitespace, and `UnexpectedEof` if the input ends part way
+/// through, leaving what was read in the buffer.
+fn read_header_block<R: BufRead>(
+    reader: &mut R,
+    header_buffer: &mut Vec<u8>,
+) -> Result<bool, RecordError> {
     // This function was adapted from the warc_reader.rs
     // module in the warc library at https://github.com/jedireza/warc
     //
@@ -293,123 +1246,395 @@ fn read_header_block<R: BufRead>(reader: &mut R) -> Option<String> {
     // TORT OR OTHERWISE, ARISING FROM, OUT OF OR IN CONNECTION WITH THE
     // SOFTWARE OR THE USE OR OTHER DEALINGS IN THE SOFTWARE.
 
-    let mut header_buffer = String::with_capacity(2048);
-    let mut found_headers = false;
+    header_buffer.clear();
 
-    while !found_headers {
+    loop {
         // Read line-by-line from the offset in a loop
         // and stop when the reader two newlines.
-        let bytes_read = reader.read_line(&mut header_buffer).unwrap();
+        let bytes_read = reader
+            .read_until(b'\n', header_buffer)
+            .map_err(RecordError::ReadError)?;
 
         if bytes_read == 0 {
-            return None;
+            // Reaching the end of the input before any header
+            // is found means there are no more records, but
+            // stopping part way through a header is an error.
+            if header_buffer.trim_ascii().is_empty() {
+                return Ok(false);
+            } else {
+                return Err(RecordError::UnexpectedEof);
+            }
         }
 
         // If the line is empty and consists only of newline
         // characters, then we've reached the end of the
-        // header block.
-        if bytes_read == 2 {
-            let last_two_chars = header_buffer.len() - 2;
-            if &header_buffer[last_two_chars..] == "\r\n" {
-                found_headers = true;
-            }
+        // header block. Some writers end lines with a bare
+        // LF rather than CRLF, so accept that too.
+        if matches!(
+            &header_buffer[header_buffer.len() - bytes_read..],
+            b"\r\n" | b"\n"
+        ) {
+            return Ok(true);
         }
     }
-    return Some(header_buffer);
 }
 
-fn process_headers(mut parsed_record: IndexRecord, buffer: &str) -> IndexRecord {
+fn process_headers(
+    mut parsed_record: IndexRecord,
+    buffer: &[u8],
+) -> Result<IndexRecord, RecordError> {
     #[derive(PartialEq)]
     enum HeaderType {
         Warc,
         Http,
+        HttpRequest,
     }
 
     // The first four characters of the buffer should be
-    // either "WARC" or "HTTP".
-    let header_first_line = buffer.get(..4).unwrap();
-    let header_type = match header_first_line {
-        "WARC" => HeaderType::Warc,
-        "HTTP" => HeaderType::Http,
-        &_ => todo!("Return an error if the first line is not WARC or HTTP"),
+    // either "WARC" or "HTTP", unless this is the request
+    // line of a request record, such as "GET / HTTP/1.1".
+    let fields_start = buffer
+        .iter()
+        .position(|byte| return *byte == b'\n')
+        .map_or(buffer.len(), |line_end| return line_end + 1);
+    let (header_first_line, fields_block) = buffer.split_at(fields_start);
+    let header_first_line = header_lines(header_first_line).next().unwrap_or_default();
+    let header_type = match buffer.get(..4) {
+        Some(b"WARC") => HeaderType::Warc,
+        Some(b"HTTP") => HeaderType::Http,
+        _ if parsed_record.record_type == Some(WarcRecordType::Request) => HeaderType::HttpRequest,
+        _ => return Err(RecordError::MalformedHeader(to_text(header_first_line))),
     };
 
+    if header_type == HeaderType::HttpRequest {
+        // The method is the first word of the request line.
+        let Some(method) = header_first_line
+            .split(u8::is_ascii_whitespace)
+            .find(|word| return !word.is_empty())
+        else {
+            return Err(RecordError::MalformedHeader(to_text(header_first_line)));
+        };
+        parsed_record.http_method = Some(String::from_utf8_lossy(method).to_ascii_uppercase());
+    }
+
     if header_type == HeaderType::Http {
-        // Get a slice between 9 and 12 bytes in,
-        // this should be the HTTP status code.
-        let raw_status_code = buffer.get(9..12).unwrap();
-        // TODO! Return 'None' or 'Error' if this doesn't work.
-        parsed_record.http_status_code = raw_status_code.parse::<usize>().unwrap();
-    }
-
-    // Iterate over the lines in the header block, skipping
-    // the first one as that's the WARC or HTTP declaration.
-    let header_iterator = buffer.trim().lines();
-
-    // Go over each field in the header to find the content-type.
-    for header_field in header_iterator.skip(1) {
-        let split_field = header_field.split_once(':').unwrap();
-        let key = split_field.0.to_ascii_lowercase();
-        let value = split_field.1.trim();
-
-        match header_type {
-            HeaderType::Warc => {
-                match key.as_str() {
-                    "content-length" => {
-                        parsed_record.content_length = value.parse::<usize>().unwrap();
-                    }
-                    "warc-payload-digest" => {
-                        parsed_record.digest = String::from_str(value).unwrap();
-                    }
-                    "warc-date" => {
-                        parsed_record.timestamp = String::from_str(value).unwrap();
-                    }
-                    "warc-target-uri" => {
-                        parsed_record.url = String::from_str(value).unwrap();
-                    }
-                    "warc-type" => {
-                        parsed_record.record_type = match value {
-                            "response" => Some(WarcRecordType::Response),
-                            "revisit" => Some(WarcRecordType::Revisit),
-                            "resource" => Some(WarcRecordType::Resource),
-                            "metadata" => Some(WarcRecordType::Metadata),
-                            // Should probably return with a defined
-                            // error if the record type is unparseable
-                            _ => None,
-                        };
-                    }
-                    "content-type" => {
-                        if value.get(..16).is_some_and(|truncated_content_type| {
-                            return truncated_content_type == "application/http";
-                        }) {
-                            // If the first 16 characters of the content type
-                            // match this then it's an HTTP resource
-                            parsed_record.is_http = true;
-                        }
-                    }
-                    _ => {
-                        // Do nothing?
-                        continue;
-                    }
-                }
+        parsed_record.http_status_code = match headers::parse_status_line(header_first_line) {
+            Some(status_code) => status_code,
+            None => return Err(RecordError::InvalidStatusLine(to_text(header_first_line))),
+        };
+    }
+
+    // Go over each field in the header to find the content-type. The
+    // first line has already been read, as that's the WARC or HTTP
+    // declaration.
+    let mut has_folded_fields = false;
+    for header_field in headers::parse_fields(fields_block) {
+        match (&header_type, header_field) {
+            (HeaderType::Warc, Ok(field)) => {
+                process_warc_field(&mut parsed_record, field.name, &field.value)?;
+            }
+            // A WARC header line which isn't a field suggests that
+            // the record is damaged, but HTTP headers were often
+            // recorded by crawlers exactly as the server sent them.
+            (HeaderType::Warc, Err(header_line)) => {
+                return Err(RecordError::MalformedHeader(to_text(header_line)));
             }
-            // If this is an HTTP header, the content-type refers to the
-            // response body, and we want to get that.
-            HeaderType::Http => {
-                if &key == "content-type" {
-                    value.clone_into(&mut parsed_record.mime_type);
+            (HeaderType::Http | HeaderType::HttpRequest, Ok(field)) => {
+                has_folded_fields |= matches!(field.value, Cow::Owned(_));
+                // If this is an HTTP header, the content-type refers to the
+                // response body, and we want to get that. For a request, it
+                // refers to the request body.
+                if field.name.eq_ignore_ascii_case(b"content-type") {
+                    parsed_record.mime_type =
+                        MimeType::parse(&String::from_utf8_lossy(&field.value));
                 }
             }
+            (HeaderType::Http | HeaderType::HttpRequest, Err(_)) => {}
         }
     }
 
-    // We additionally want to know, if the content-type
-    // is "text/html", and the status code was successful,
-    // set the is_page value to true.
-    if parsed_record.mime_type == "text/html"
-        && (200..299).contains(&parsed_record.http_status_code)
+    // Request records are only kept until they're paired
+    // with their response, so their headers aren't needed.
+    if header_type == HeaderType::Http {
+        parsed_record.http_headers = HeaderMap::from_block(fields_block, has_folded_fields);
+    }
+
+    // Records which will end up in the index need a date.
+    if header_type == HeaderType::Warc
+        && parsed_record.record_type.is_some()
+        && parsed_record.timestamp.is_empty()
+    {
+        return Err(RecordError::MissingHeader("WARC-Date".to_owned()));
+    }
+
+    // Resource and metadata records don't have an HTTP status,
+    // so give them one to show that the content is available.
+    if header_type == HeaderType::Warc
+        && [
+            Some(WarcRecordType::Resource),
+            Some(WarcRecordType::Metadata),
+        ]
+        .contains(&parsed_record.record_type)
     {
-        parsed_record.is_page = true;
+        parsed_record.http_status_code = RESOURCE_STATUS_CODE;
+    }
+
+    // Revisits don't always include the HTTP headers of the response, in
+    // which case set a status based on the profile. This is replaced by
+    // the real status if there are HTTP headers to read.
+    if header_type == HeaderType::Warc && parsed_record.record_type == Some(WarcRecordType::Revisit)
+    {
+        parsed_record.http_status_code = match parsed_record.revisit_profile {
+            Some(RevisitProfile::ServerNotModified) => NOT_MODIFIED_STATUS_CODE,
+            Some(RevisitProfile::IdenticalPayloadDigest) | None => RESOURCE_STATUS_CODE,
+        };
+    }
+    return Ok(parsed_record);
+}
+
+/// Fill in the record from a single field of a WARC header block.
+fn process_warc_field(
+    parsed_record: &mut IndexRecord,
+    key: &[u8],
+    value: &[u8],
+) -> Result<(), RecordError> {
+    // Field names aren't case sensitive. The ones we're interested
+    // in are short, so lowercase them here rather than allocating.
+    let mut key_buffer = [0; 32];
+    let Some(lowercase_key) = key_buffer.get_mut(..key.len()) else {
+        return Ok(());
+    };
+    lowercase_key.copy_from_slice(key);
+    lowercase_key.make_ascii_lowercase();
+
+    match &*lowercase_key {
+        b"content-length" => {
+            parsed_record.content_length = match std::str::from_utf8(value)
+                .ok()
+                .and_then(|content_length| return content_length.parse::<usize>().ok())
+            {
+                Some(content_length) => content_length,
+                None => {
+                    return Err(RecordError::InvalidHeaderValue {
+                        field: "Content-Length".to_owned(),
+                        value: to_text(value),
+                    });
+                }
+            };
+        }
+        b"warc-payload-digest" => {
+            parsed_record.digest = to_text(value);
+        }
+        b"warc-date" => {
+            // Check the date here, so that writing
+            // out the index later on can't fail.
+            let Some(date) = to_date(value) else {
+                return Err(RecordError::InvalidHeaderValue {
+                    field: "WARC-Date".to_owned(),
+                    value: to_text(value),
+                });
+            };
+            date.clone_into(&mut parsed_record.timestamp);
+        }
+        b"warc-target-uri" => {
+            parsed_record.url = to_text(strip_angle_brackets(value));
+        }
+        b"warc-refers-to-target-uri" => {
+            parsed_record.refers_to_url = Some(to_text(strip_angle_brackets(value)));
+        }
+        // This date isn't needed to index the record,
+        // so leave it out if it's not valid.
+        b"warc-refers-to-date" => {
+            parsed_record.refers_to_date = to_date(value).map(str::to_owned);
+        }
+        b"warc-profile" => {
+            // The profile URI changes with the WARC version,
+            // but always ends with the name of the profile.
+            parsed_record.revisit_profile = if value.ends_with(b"/identical-payload-digest") {
+                Some(RevisitProfile::IdenticalPayloadDigest)
+            } else if value.ends_with(b"/server-not-modified") {
+                Some(RevisitProfile::ServerNotModified)
+            } else {
+                None
+            };
+        }
+        b"warc-record-id" => {
+            parsed_record.record_id = to_text(value);
+        }
+        b"warc-concurrent-to" => {
+            parsed_record.concurrent_to = to_text(value);
+        }
+        b"warc-type" => {
+            parsed_record.record_type = match value {
+                b"response" => Some(WarcRecordType::Response),
+                b"revisit" => Some(WarcRecordType::Revisit),
+                b"resource" => Some(WarcRecordType::Resource),
+                b"metadata" => Some(WarcRecordType::Metadata),
+                b"request" => Some(WarcRecordType::Request),
+                // Other record types, such as warcinfo,
+                // are valid but are not indexed.
+                _ => None,
+            };
+        }
+        // If the content type starts with this
+        // then it's an HTTP resource
+        b"content-type" if value.starts_with(b"application/http") => {
+            parsed_record.is_http = true;
+        }
+        // Otherwise, the content type describes the record block itself,
+        // as in a resource record holding a screenshot or a text file.
+        b"content-type" => {
+            parsed_record.mime_type = MimeType::parse(&String::from_utf8_lossy(value));
+        }
+        _ => {
+            // Do nothing?
+        }
+    }
+    return Ok(());
+}
+
+/// An error raised while indexing a WARC file.
+#[derive(Debug)]
+pub enum IndexerError {
+    /// The WARC file could not be opened.
+    FileReadError { file_name: String, error: io::Error },
+    /// The file doesn't start with a WARC record, either
+    /// uncompressed or gzipped.
+    UnrecognisedFormatError { file_name: String },
+    /// A record in the WARC file could not be read, `offset` is the
+    /// byte offset of the start of the record within the file.
+    RecordError {
+        file_name: String,
+        offset: usize,
+        reason: RecordError,
+    },
+}
+impl fmt::Display for IndexerError {
+    fn fmt(&self, message: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FileReadError { file_name, error } => {
+                return write!(message, "Could not read {file_name}: {error}");
+            }
+            Self::UnrecognisedFormatError { file_name } => {
+                return write!(message, "{file_name} is not a WARC file");
+            }
+            Self::RecordError {
+                file_name,
+                offset,
+                reason,
+            } => {
+                return write!(
+                    message,
+                    "Could not index record at byte {offset} of {file_name}: {reason}"
+                );
+            }
+        }
+    }
+}
+impl Error for IndexerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::FileReadError { error, .. } => return Some(error),
+            Self::RecordError { reason, .. } => return Some(reason),
+            Self::UnrecognisedFormatError { .. } => return None,
+        }
+    }
+}
+
+/// A region of a WARC file which was skipped over by the [`lenient_indexer`].
+#[derive(Debug)]
+pub struct SkippedRegion {
+    pub file_name: String,
+    /// Byte offset of the start of the region within the file.
+    pub offset: usize,
+    /// Length of the region in bytes.
+    pub length: usize,
+    /// Why the first record in the region could not be read.
+    pub reason: RecordError,
+}
+impl fmt::Display for SkippedRegion {
+    fn fmt(&self, message: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(
+            message,
+            "Skipped {} bytes at byte {} of {}: {}",
+            self.length, self.offset, self.file_name, self.reason
+        );
+    }
+}
+
+/// The reason a single WARC record could not be read.
+#[derive(Debug)]
+pub enum RecordError {
+    /// Reading or decompressing the file failed.
+    ReadError(io::Error),
+    /// The file ended part way through a header block.
+    UnexpectedEof,
+    /// The record did not start with one of the [`SUPPORTED_WARC_VERSIONS`].
+    UnsupportedVersion(String),
+    /// A header line could not be split into a name and value.
+    MalformedHeader(String),
+    /// The HTTP status line did not contain a valid status code.
+    InvalidStatusLine(String),
+    /// A header which the indexer relies on was missing.
+    MissingHeader(String),
+    /// A header value could not be parsed.
+    InvalidHeaderValue { field: String, value: String },
+    /// The record did not end where its Content-Length said it would.
+    InvalidRecordLength(usize),
+    /// The Content-Length of the record at this offset was too
+    /// large for the end of the record to be worked out.
+    ContentLengthOverflow {
+        offset: usize,
+        content_length: usize,
+    },
+}
+impl fmt::Display for RecordError {
+    fn fmt(&self, message: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::ReadError(error_message) => {
+                return write!(message, "read error: {error_message}");
+            }
+            Self::UnexpectedEof => {
+                return write!(message, "unexpected end of file in header block");
+            }
+            Self::UnsupportedVersion(first_line) => {
+                return write!(message, "unsupported WARC version in \"{first_line}\"");
+            }
+            Self::MalformedHeader(header_line) => {
+                return write!(message, "malformed header line \"{header_line}\"");
+            }
+            Self::InvalidStatusLine(status_line) => {
+                return write!(message, "invalid HTTP status line \"{status_line}\"");
+            }
+            Self::MissingHeader(field) => {
+                return write!(message, "missing {field} header");
+            }
+            Self::InvalidHeaderValue { field, value } => {
+                return write!(message, "invalid {field} value \"{value}\"");
+            }
+            Self::InvalidRecordLength(content_length) => {
+                return write!(
+                    message,
+                    "record does not end after its Content-Length of {content_length} bytes"
+                );
+            }
+            Self::ContentLengthOverflow {
+                offset,
+                content_length,
+            } => {
+                return write!(
+                    message,
+                    "Content-Length of {content_length} bytes for the record at {offset} is too large"
+                );
+            }
+        }
+    }
+}
+impl Error for RecordError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::ReadError(read_error) => return Some(read_error),
+            _ => return None,
+        }
     }
-    return parsed_record;
 }
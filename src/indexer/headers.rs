@@ -0,0 +1,203 @@
+// Parsing for the fields of WARC and HTTP header blocks, which is
+// forgiving of the many ways crawlers have recorded HTTP headers.
+use std::borrow::Cow;
+
+/// The HTTP header fields of a record, in the order they appear.
+///
+/// Fields which appear more than once, like `Set-Cookie`, are all kept.
+/// Names are looked up without regard to case.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HeaderMap {
+    /// The header block after the status line, one field to a line.
+    /// Fields are only split out when they're looked up, so that
+    /// keeping a record's headers takes up one allocation.
+    text: String,
+}
+impl HeaderMap {
+    pub(super) const fn new() -> Self {
+        return Self {
+            text: String::new(),
+        };
+    }
+
+    /// Keep the fields of a header block, replacing anything which isn't
+    /// UTF-8. Most blocks are kept as they are, but if the caller found
+    /// folded fields when parsing them, these are joined back on to one
+    /// line.
+    pub(super) fn from_block(header_block: &[u8], has_folded_fields: bool) -> Self {
+        if !has_folded_fields {
+            return Self {
+                text: String::from_utf8_lossy(header_block).into_owned(),
+            };
+        }
+
+        let mut unfolded_block = Vec::with_capacity(header_block.len());
+        for field in parse_fields(header_block).flatten() {
+            unfolded_block.extend_from_slice(field.name);
+            unfolded_block.push(b':');
+            unfolded_block.extend_from_slice(&field.value);
+            unfolded_block.push(b'\n');
+        }
+        return Self {
+            text: String::from_utf8_lossy(&unfolded_block).into_owned(),
+        };
+    }
+
+    /// The value of the first field with this name.
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<&str> {
+        return self
+            .iter()
+            .find(|(field_name, _)| return field_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| return value);
+    }
+
+    /// The values of every field with this name.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> {
+        return self
+            .iter()
+            .filter(move |(field_name, _)| return field_name.eq_ignore_ascii_case(name))
+            .map(|(_, value)| return value);
+    }
+
+    /// Every field as a name and value. Lines which
+    /// aren't a field are left out.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        return self.text.lines().filter_map(|header_line| {
+            let (name, value) = header_line.split_once(':')?;
+            return Some((name.trim(), value.trim()));
+        });
+    }
+
+    #[must_use]
+    pub fn len(&self) -> usize {
+        return self.iter().count();
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        return self.iter().next().is_none();
+    }
+}
+
+/// A single field from a header block.
+#[derive(Debug, PartialEq, Eq)]
+pub struct HeaderField<'a> {
+    pub name: &'a [u8],
+    /// Only allocated if the field was folded over several lines.
+    pub value: Cow<'a, [u8]>,
+}
+
+/// Split the lines of a header block into fields. Continuation lines,
+/// which start with a space or tab, are joined on to the field before
+/// them with a single space. Lines which aren't a field are returned as
+/// an error, for the caller to decide whether they matter.
+///
+/// Fields are split out as they're iterated over, so that a header
+/// block can be read without allocating.
+pub fn parse_fields(header_block: &[u8]) -> impl Iterator<Item = Result<HeaderField<'_>, &[u8]>> {
+    let mut remaining_block = header_block;
+    return std::iter::from_fn(move || {
+        let mut header_line = take_line(&mut remaining_block)?;
+        while header_line.is_empty() {
+            header_line = take_line(&mut remaining_block)?;
+        }
+        let Some(colon_position) = header_line.iter().position(|byte| return *byte == b':') else {
+            return Some(Err(header_line));
+        };
+        let mut field = HeaderField {
+            name: header_line[..colon_position].trim_ascii(),
+            value: Cow::Borrowed(header_line[colon_position + 1..].trim_ascii()),
+        };
+        while matches!(remaining_block.first(), Some(b' ' | b'\t')) {
+            let continuation = take_line(&mut remaining_block)
+                .unwrap_or_default()
+                .trim_ascii();
+            if !continuation.is_empty() {
+                let value = field.value.to_mut();
+                value.push(b' ');
+                value.extend_from_slice(continuation);
+            }
+        }
+        return Some(Ok(field));
+    });
+}
+
+/// Take the next line off the front of a header block, without
+/// its line ending, or `None` if there's nothing left.
+fn take_line<'a>(remaining_block: &mut &'a [u8]) -> Option<&'a [u8]> {
+    if remaining_block.is_empty() {
+        return None;
+    }
+    let line_length = remaining_block
+        .iter()
+        .position(|byte| return *byte == b'\n')
+        .map_or(remaining_block.len(), |line_end| return line_end + 1);
+    let (header_line, rest) = remaining_block.split_at(line_length);
+    *remaining_block = rest;
+    let header_line = header_line.strip_suffix(b"\n").unwrap_or(header_line);
+    return Some(header_line.strip_suffix(b"\r").unwrap_or(header_line));
+}
+
+/// Get the status code from an HTTP status line, such as `HTTP/1.1 200 OK`.
+/// Any protocol version is accepted, including `HTTP/2` and `HTTP/3`, and
+/// the reason phrase can be left out.
+pub fn parse_status_line(status_line: &[u8]) -> Option<usize> {
+    let after_protocol = status_line.strip_prefix(b"HTTP/")?;
+    let version_length = after_protocol.iter().position(u8::is_ascii_whitespace)?;
+    let (status_code, reason_phrase) = after_protocol[version_length..]
+        .trim_ascii_start()
+        .split_at_checked(3)?;
+    if !reason_phrase.first().is_none_or(u8::is_ascii_whitespace) {
+        return None;
+    }
+    return status_code.iter().try_fold(0, |code, digit| {
+        return digit
+            .is_ascii_digit()
+            .then(|| return code * 10 + usize::from(digit - b'0'));
+    });
+}
+
+#[test]
+fn status_lines() {
+    let test_cases: [(&[u8], Option<usize>); 9] = [
+        (b"HTTP/1.1 200 OK", Some(200)),
+        (b"HTTP/1.0 404 Not Found", Some(404)),
+        (b"HTTP/2 301", Some(301)),
+        (b"HTTP/2.0 204 ", Some(204)),
+        (b"HTTP/1.1  302  Found", Some(302)),
+        (b"HTTP/1.1 2000 OK", None),
+        (b"HTTP/1.1 OK", None),
+        (b"HTTP/1.1 2x0 OK", None),
+        (b"ICY 200 OK", None),
+    ];
+
+    for test_case in test_cases {
+        assert_eq!(parse_status_line(test_case.0), test_case.1);
+    }
+}
+
+#[test]
+fn header_fields() {
+    let header_block = b"Content-Type: text/html\r\nX-Folded: first\r\n  second\r\n\tthird\r\nnot a header\r\n\r\nSet-Cookie:a=1\nset-cookie: b=2\r\n\r\n";
+    let fields: Vec<_> = parse_fields(header_block).collect();
+
+    assert_eq!(fields.len(), 5);
+    assert_eq!(
+        fields[1],
+        Ok(HeaderField {
+            name: b"X-Folded",
+            value: Cow::Owned(b"first second third".to_vec()),
+        })
+    );
+    assert_eq!(fields[2], Err(b"not a header".as_slice()));
+
+    let header_map = HeaderMap::from_block(header_block, true);
+    assert_eq!(header_map.len(), 4);
+    assert_eq!(header_map.get("content-type"), Some("text/html"));
+    assert_eq!(header_map.get("x-folded"), Some("first second third"));
+    assert_eq!(
+        header_map.get_all("Set-Cookie").collect::<Vec<_>>(),
+        ["a=1", "b=2"]
+    );
+}
@@ -0,0 +1,457 @@
+// Reads the HTML payload of page records, undoing any transfer and
+// content encoding, so that the title and text can be listed in
+// pages.jsonl.
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+use flate2::bufread::{DeflateDecoder, GzDecoder, ZlibDecoder};
+use std::io::{self, BufRead, BufReader, Read};
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+use crate::indexer::IndexRecord;
+
+/// Only this much of a page is read, after decompression, when looking
+/// for its title. Titles are in the `<head>`, so are usually near the
+/// start, and this keeps huge pages from slowing down indexing.
+pub const MAX_HTML_SIZE: usize = 262_144;
+
+/// When extracting text, this much of a page is read. Markup and
+/// scripts usually make up most of a page, so this is well over
+/// the size of text which is kept.
+pub const MAX_TEXT_HTML_SIZE: usize = 4_194_304;
+
+/// The contents of these elements aren't visible text. Scripts and
+/// styles in particular would fill up the text of most pages.
+const HIDDEN_ELEMENTS: [&str; 8] = [
+    "head", "script", "style", "noscript", "template", "title", "svg", "math",
+];
+
+/// Elements which sit inside a line of text, so aren't
+/// separated from the text around them by a space.
+const INLINE_ELEMENTS: [&str; 25] = [
+    "a", "abbr", "b", "bdi", "bdo", "cite", "code", "data", "dfn", "em", "font", "i", "kbd",
+    "mark", "q", "s", "samp", "small", "span", "strong", "sub", "sup", "time", "u", "var",
+];
+
+/// Browsers only look this far into a page for a `<meta>` charset.
+const CHARSET_SNIFF_SIZE: usize = 1024;
+
+/// Read the title of an HTML page from the payload of its record,
+/// which the reader should be at the start of. Payloads which can't
+/// be decoded, or have no title, give `None`.
+pub fn read_title<R: BufRead>(record: &IndexRecord, payload_reader: &mut R) -> Option<String> {
+    let html = read_html(record, payload_reader, MAX_HTML_SIZE)?;
+    return extract_title(&html);
+}
+
+/// Read up to `max_size` bytes of an HTML payload as text. The
+/// content is dechunked and decompressed as the HTTP headers say,
+/// then decoded from the charset given in the headers or the page.
+pub fn read_html<R: BufRead>(
+    record: &IndexRecord,
+    payload_reader: &mut R,
+    max_size: usize,
+) -> Option<String> {
+    let http_headers = record.http_headers();
+
+    // Some crawlers store the payload as it was sent, and others store it
+    // dechunked without changing the headers, so check it really is chunked.
+    let is_chunked = http_headers
+        .get_all("transfer-encoding")
+        .any(|transfer_encoding| return transfer_encoding.to_ascii_lowercase().contains("chunked"));
+    let mut payload: Box<dyn BufRead + '_> = if is_chunked && starts_with_chunk(payload_reader) {
+        Box::new(BufReader::new(ChunkedReader::new(payload_reader)))
+    } else {
+        Box::new(payload_reader)
+    };
+
+    let content_encoding = http_headers
+        .get("content-encoding")
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    let mut decoder: Box<dyn Read + '_> = match content_encoding.as_str() {
+        "" | "identity" => Box::new(payload),
+        "gzip" | "x-gzip" => Box::new(GzDecoder::new(payload)),
+        // Servers disagree on whether deflate has a zlib wrapper,
+        // so look for the zlib header.
+        "deflate" => {
+            let start_of_payload = payload.fill_buf().ok()?;
+            if is_zlib_header(start_of_payload) {
+                Box::new(ZlibDecoder::new(payload))
+            } else {
+                Box::new(DeflateDecoder::new(payload))
+            }
+        }
+        "zstd" => Box::new(ZstdDecoder::with_buffer(payload).ok()?),
+        // Brotli and anything else we can't decode.
+        _ => return None,
+    };
+
+    // A payload which is cut short, or fails to decompress part way
+    // through, can still have a title in what was read before that.
+    let mut html = Vec::with_capacity(max_size.min(record.content_length).min(16_384));
+    let read_result = decoder
+        .by_ref()
+        .take(max_size as u64)
+        .read_to_end(&mut html);
+    if read_result.is_err() && html.is_empty() {
+        return None;
+    }
+
+    let content_type_charset = record
+        .mime_type()
+        .charset()
+        .and_then(|label| return Encoding::for_label(label.as_bytes()));
+    return Some(decode_html(&html, content_type_charset));
+}
+
+/// Decode HTML as text. A byte order mark comes first, then the charset
+/// from the Content-Type, then a `<meta>` charset in the page. Without
+/// any of those, pages which aren't valid UTF-8 are read as Windows-1252.
+fn decode_html(html: &[u8], content_type_charset: Option<&'static Encoding>) -> String {
+    let encoding = content_type_charset
+        .or_else(|| return meta_charset(html))
+        .unwrap_or_else(|| {
+            if std::str::from_utf8(html).is_ok() {
+                return UTF_8;
+            } else {
+                return WINDOWS_1252;
+            }
+        });
+    // This also checks for a byte order mark, which takes precedence.
+    let (text, _, _) = encoding.decode(html);
+    return text.into_owned();
+}
+
+/// Find a charset in a `<meta charset="...">` or `<meta http-equiv=
+/// "Content-Type" content="...; charset=...">` tag near the start of
+/// a page.
+fn meta_charset(html: &[u8]) -> Option<&'static Encoding> {
+    let start_of_html = html[..html.len().min(CHARSET_SNIFF_SIZE)].to_ascii_lowercase();
+    return start_of_html
+        .split(|byte| return *byte == b'<')
+        .filter_map(|tag| return tag.strip_prefix(b"meta"))
+        .find_map(|meta_tag| {
+            let meta_tag = meta_tag.split(|byte| return *byte == b'>').next()?;
+            let charset_position = find_bytes(meta_tag, b"charset=")?;
+            let value = meta_tag[charset_position + b"charset=".len()..].trim_ascii_start();
+            let label = value
+                .strip_prefix(b"\"")
+                .or_else(|| return value.strip_prefix(b"'"))
+                .unwrap_or(value);
+            let label_length = label
+                .iter()
+                .position(|byte| {
+                    return matches!(byte, b'"' | b'\'' | b';' | b'/')
+                        || byte.is_ascii_whitespace();
+                })
+                .unwrap_or(label.len());
+            return Encoding::for_label(&label[..label_length]);
+        });
+}
+
+/// Pick out the text of the `<title>` element, with character
+/// references decoded and runs of whitespace collapsed.
+pub fn extract_title(html: &str) -> Option<String> {
+    // Lowercasing only changes ASCII letters, so
+    // positions in this are the same as in the HTML.
+    let lowercase_html = html.to_ascii_lowercase();
+
+    let mut search_start = 0;
+    let title_start = loop {
+        let tag_position = search_start + lowercase_html[search_start..].find("<title")?;
+        let after_tag_name = tag_position + "<title".len();
+        // Skip over tags like `<titlebar>`.
+        match lowercase_html.as_bytes().get(after_tag_name) {
+            Some(b'>' | b'/') => break after_tag_name,
+            Some(byte) if byte.is_ascii_whitespace() => break after_tag_name,
+            _ => search_start = after_tag_name,
+        }
+    };
+    let text_start = title_start + lowercase_html[title_start..].find('>')? + 1;
+    let text_end = text_start + lowercase_html[text_start..].find("</title")?;
+
+    let title = decode_character_references(&html[text_start..text_end])
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ");
+    return (!title.is_empty()).then_some(title);
+}
+
+/// Pick out the visible text of a page, leaving out markup, comments and
+/// the contents of elements like `<script>` and `<style>`. Whitespace is
+/// collapsed, and the text is cut short at `max_size` bytes. Pages with
+/// no text give `None`.
+pub fn extract_text(html: &str, max_size: usize) -> Option<String> {
+    // As with titles, positions in this are the same as in the HTML.
+    let lowercase_html = html.to_ascii_lowercase();
+    let html_bytes = lowercase_html.as_bytes();
+    let mut text = String::with_capacity(html.len().min(max_size));
+    let mut position = 0;
+
+    while let Some(tag_offset) = lowercase_html[position..].find('<') {
+        let tag_start = position + tag_offset;
+        text.push_str(&html[position..tag_start]);
+
+        if lowercase_html[tag_start..].starts_with("<!--") {
+            position = lowercase_html[tag_start..]
+                .find("-->")
+                .map_or(html.len(), |comment_end| return tag_start + comment_end + 3);
+            continue;
+        }
+
+        // Anything else which isn't a tag, like a `<` on its own, is text.
+        let is_end_tag = html_bytes.get(tag_start + 1) == Some(&b'/');
+        let name_start = tag_start + 1 + usize::from(is_end_tag);
+        let is_tag = html_bytes
+            .get(name_start)
+            .is_some_and(|byte| return byte.is_ascii_alphabetic() || matches!(byte, b'!' | b'?'));
+        if !is_tag {
+            text.push('<');
+            position = tag_start + 1;
+            continue;
+        }
+
+        let name_end = html_bytes[name_start..]
+            .iter()
+            .position(|byte| return !byte.is_ascii_alphanumeric())
+            .map_or(html.len(), |name_length| return name_start + name_length);
+        let tag_name = &lowercase_html[name_start..name_end];
+        position = tag_end(html_bytes, name_end);
+
+        if !is_end_tag && HIDDEN_ELEMENTS.contains(&tag_name) {
+            // Skip to the end of the element, which ends the page if
+            // it's never closed.
+            position = lowercase_html[position..]
+                .find(&format!("</{tag_name}"))
+                .map_or(html.len(), |end_tag_offset| {
+                    return tag_end(html_bytes, position + end_tag_offset);
+                });
+        }
+        if !INLINE_ELEMENTS.contains(&tag_name) {
+            text.push(' ');
+        }
+    }
+    text.push_str(&html[position..]);
+
+    let mut text = decode_character_references(&text)
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ");
+    if text.len() > max_size {
+        let mut cut_position = max_size;
+        while !text.is_char_boundary(cut_position) {
+            cut_position -= 1;
+        }
+        text.truncate(cut_position);
+        text.truncate(text.trim_end().len());
+    }
+    return (!text.is_empty()).then_some(text);
+}
+
+/// Find the position just after the `>` which closes a tag,
+/// skipping over any `>` in quoted attribute values.
+fn tag_end(html: &[u8], start: usize) -> usize {
+    let mut quote = None;
+    for (position, byte) in html.iter().enumerate().skip(start) {
+        match (quote, byte) {
+            (None, b'>') => return position + 1,
+            (None, b'"' | b'\'') => quote = Some(*byte),
+            (Some(quote_byte), _) if quote_byte == *byte => quote = None,
+            _ => {}
+        }
+    }
+    return html.len();
+}
+
+/// Decode numeric character references, and the named ones which are
+/// common in titles. Anything else is left as it is.
+fn decode_character_references(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(ampersand_position) = rest.find('&') {
+        decoded.push_str(&rest[..ampersand_position]);
+        rest = &rest[ampersand_position..];
+
+        let character = rest
+            .find(';')
+            .filter(|semicolon_position| return *semicolon_position <= 10)
+            .and_then(|semicolon_position| {
+                let character = match &rest[1..semicolon_position] {
+                    "amp" => '&',
+                    "lt" => '<',
+                    "gt" => '>',
+                    "quot" => '"',
+                    "apos" => '\'',
+                    "nbsp" => '\u{a0}',
+                    reference => {
+                        let number = reference.strip_prefix('#')?;
+                        let code_point = match number.strip_prefix(['x', 'X']) {
+                            Some(hex_number) => u32::from_str_radix(hex_number, 16).ok()?,
+                            None => number.parse().ok()?,
+                        };
+                        char::from_u32(code_point)?
+                    }
+                };
+                return Some((character, semicolon_position));
+            });
+
+        if let Some((character, semicolon_position)) = character {
+            decoded.push(character);
+            rest = &rest[semicolon_position + 1..];
+        } else {
+            decoded.push('&');
+            rest = &rest[1..];
+        }
+    }
+    decoded.push_str(rest);
+    return decoded;
+}
+
+/// Whether the payload starts with a chunk size line.
+fn starts_with_chunk<R: BufRead>(payload_reader: &mut R) -> bool {
+    return payload_reader.fill_buf().is_ok_and(|start_of_payload| {
+        return start_of_payload
+            .split(|byte| return *byte == b'\n')
+            .next()
+            .is_some_and(|first_line| return chunk_size(first_line).is_some());
+    });
+}
+
+/// Parse a chunk size line, which is a hexadecimal
+/// number optionally followed by extensions.
+fn chunk_size(line: &[u8]) -> Option<u64> {
+    let size = line
+        .split(|byte| return *byte == b';')
+        .next()
+        .unwrap_or_default()
+        .trim_ascii();
+    if size.is_empty() || !size.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    return u64::from_str_radix(std::str::from_utf8(size).ok()?, 16).ok();
+}
+
+/// A zlib stream starts with a two byte header, which as
+/// a big-endian number is a multiple of 31.
+fn is_zlib_header(start_of_payload: &[u8]) -> bool {
+    return match start_of_payload {
+        [first_byte, second_byte, ..] => {
+            first_byte & 0x0f == 8 && u16::from_be_bytes([*first_byte, *second_byte]) % 31 == 0
+        }
+        _ => false,
+    };
+}
+
+fn find_bytes(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    return haystack
+        .windows(needle.len())
+        .position(|window| return window == needle);
+}
+
+/// Undoes chunked transfer encoding. Chunk extensions and
+/// trailer fields are ignored.
+struct ChunkedReader<R> {
+    reader: R,
+    /// The number of bytes left to read in the current chunk.
+    chunk_remaining: u64,
+    /// Whether a chunk has been read, so that the line ending
+    /// after its data needs to be skipped.
+    in_chunks: bool,
+    finished: bool,
+}
+impl<R: BufRead> ChunkedReader<R> {
+    const fn new(reader: R) -> Self {
+        return Self {
+            reader,
+            chunk_remaining: 0,
+            in_chunks: false,
+            finished: false,
+        };
+    }
+
+    fn read_line(&mut self) -> io::Result<Vec<u8>> {
+        let mut line = Vec::with_capacity(16);
+        self.reader.read_until(b'\n', &mut line)?;
+        return Ok(line);
+    }
+}
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        if self.chunk_remaining == 0 && !self.finished {
+            if self.in_chunks {
+                self.read_line()?;
+            }
+            self.in_chunks = true;
+            let size_line = self.read_line()?;
+            self.chunk_remaining = chunk_size(&size_line)
+                .ok_or_else(|| return io::Error::from(io::ErrorKind::InvalidData))?;
+            self.finished = self.chunk_remaining == 0;
+        }
+        if self.finished {
+            return Ok(0);
+        }
+
+        let read_length = buffer
+            .len()
+            .min(usize::try_from(self.chunk_remaining).unwrap_or(usize::MAX));
+        let bytes_read = self.reader.read(&mut buffer[..read_length])?;
+        // A payload which stops part way through a chunk just ends there.
+        if bytes_read == 0 {
+            self.finished = true;
+        }
+        self.chunk_remaining -= bytes_read as u64;
+        return Ok(bytes_read);
+    }
+}
+
+#[test]
+fn html_titles() {
+    let test_cases = [
+        ("<title>Example Domain</title>", Some("Example Domain")),
+        (
+            "<html><HEAD><Title lang=en>\n  Fish &amp; Chips &#8212;\tmenu </TITLE>",
+            Some("Fish & Chips — menu"),
+        ),
+        ("<titlebar>No</titlebar><title>Yes</title>", Some("Yes")),
+        ("<title>   </title>", None),
+        ("<title>Never closed", None),
+        ("<p>No title here</p>", None),
+    ];
+    for test_case in test_cases {
+        assert_eq!(extract_title(test_case.0).as_deref(), test_case.1);
+    }
+
+    let latin1_page = b"<meta http-equiv=\"Content-Type\" content=\"text/html; charset=iso-8859-1\"><title>Caf\xe9</title>";
+    assert_eq!(
+        extract_title(&decode_html(latin1_page, None)),
+        Some("Café".to_owned())
+    );
+    assert_eq!(
+        extract_title(&decode_html(
+            b"<meta charset='utf-8'><title>Caf\xc3\xa9</title>",
+            None
+        )),
+        Some("Café".to_owned())
+    );
+
+    let page = r#"<!DOCTYPE html><html><head><title>Ignored</title>
+        <style>p { color: red; }</style></head>
+        <body><h1>Fish&nbsp;&amp; Chips</h1><!-- <p>hidden</p> -->
+        <p>Fresh <b>da</b>ily, <a href="/menu" title="a > b">menu</a></p>
+        <script>var html = "<p>not text</p>";</script>
+        <div>1 < 2</div></body></html>"#;
+    assert_eq!(
+        extract_text(page, 1000).as_deref(),
+        Some("Fish & Chips Fresh daily, menu 1 < 2")
+    );
+    assert_eq!(extract_text(page, 7).as_deref(), Some("Fish &"));
+    assert_eq!(extract_text("<p>Café</p>", 4).as_deref(), Some("Caf"));
+    assert_eq!(extract_text("<script>only a script", 100), None);
+
+    let mut chunked = String::new();
+    ChunkedReader::new(b"4;ext=1\r\nWiki\r\n5\r\npedia\r\n0\r\n\r\n".as_slice())
+        .read_to_string(&mut chunked)
+        .unwrap();
+    assert_eq!(chunked, "Wikipedia");
+}
@@ -0,0 +1,91 @@
+// Splits a Content-Type value into its essence and parameters, see
+// https://mimesniff.spec.whatwg.org/#understanding-mime-types
+
+/// A parsed Content-Type, such as `text/html; charset=UTF-8`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MimeType {
+    /// The whole value, with the essence in lower case. Most parameters
+    /// are never looked at, so they're only split up when one is asked
+    /// for, and a record's type takes up one allocation.
+    content_type: String,
+    /// Where the essence ends in `content_type`.
+    essence_length: usize,
+}
+impl MimeType {
+    #[must_use]
+    pub const fn new() -> Self {
+        return Self {
+            content_type: String::new(),
+            essence_length: 0,
+        };
+    }
+
+    /// Parse a Content-Type header value.
+    #[must_use]
+    pub fn parse(content_type: &str) -> Self {
+        let content_type = content_type.trim();
+        let essence = content_type
+            .split(';')
+            .next()
+            .unwrap_or_default()
+            .trim_end();
+        let mut content_type = content_type.to_owned();
+        content_type[..essence.len()].make_ascii_lowercase();
+        return Self {
+            content_type,
+            essence_length: essence.len(),
+        };
+    }
+
+    /// The type and subtype in lower case, such as `text/html`.
+    /// This is what's written to the `mime` field of the index.
+    #[must_use]
+    pub fn essence(&self) -> &str {
+        return &self.content_type[..self.essence_length];
+    }
+
+    /// The value of the first parameter with this name, without any
+    /// quotes around it. Names are matched without regard to case, and
+    /// parameters without a value are left out.
+    #[must_use]
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        // The first segment is anything between the essence and the first `;`.
+        let parameters = &self.content_type[self.essence_length..];
+        return parameters.split(';').skip(1).find_map(|segment| {
+            let (parameter_name, value) = segment.split_once('=')?;
+            if !parameter_name.trim().eq_ignore_ascii_case(name) {
+                return None;
+            }
+            let value = value.trim();
+            return Some(
+                value
+                    .strip_prefix('"')
+                    .and_then(|quoted_value| return quoted_value.strip_suffix('"'))
+                    .unwrap_or(value),
+            );
+        });
+    }
+
+    /// The character encoding of the content, if one was given.
+    #[must_use]
+    pub fn charset(&self) -> Option<&str> {
+        return self.parameter("charset");
+    }
+}
+
+#[test]
+fn mime_types() {
+    let html = MimeType::parse("Text/HTML; Charset=\"UTF-8\"");
+    assert_eq!(html.essence(), "text/html");
+    assert_eq!(html.charset(), Some("UTF-8"));
+
+    let multipart = MimeType::parse("multipart/form-data;boundary=AbC ; flag");
+    assert_eq!(multipart.essence(), "multipart/form-data");
+    assert_eq!(multipart.parameter("boundary"), Some("AbC"));
+    assert_eq!(multipart.parameter("flag"), None);
+    assert_eq!(multipart.parameter("BOUNDARY"), Some("AbC"));
+    assert_eq!(multipart.charset(), None);
+
+    assert_eq!(MimeType::parse("").essence(), "");
+    assert_eq!(MimeType::parse(" image/png ").essence(), "image/png");
+}
@@ -0,0 +1,274 @@
+// Decides which records are listed in pages.jsonl, and which of
+// those are seeds.
+use std::{collections::HashSet, fmt, ops::RangeInclusive};
+
+use crate::indexer::IndexRecord;
+
+/// Chooses which records in the index are pages, to be listed in
+/// `pages.jsonl`. Pass one into [`WaczOptions`](crate::WaczOptions) to
+/// change the default, which is [`HtmlPages`].
+///
+/// [`PagePolicy`] covers the usual choices of media type, status and
+/// host, but anything else can be done by implementing this trait. For
+/// example, to only list pages under one path:
+///
+/// ```
+/// use wacksy::indexer::{HtmlPages, IndexRecord, PageSelector};
+///
+/// #[derive(Debug)]
+/// struct BlogPages;
+/// impl PageSelector for BlogPages {
+///     fn is_page(&self, record: &IndexRecord) -> bool {
+///         return HtmlPages.is_page(record) && record.url().contains("/blog/");
+///     }
+/// }
+/// ```
+pub trait PageSelector: fmt::Debug + Send + Sync {
+    /// Whether a record should be listed as a page.
+    fn is_page(&self, record: &IndexRecord) -> bool;
+}
+
+/// The default [`PageSelector`], which picks out HTML
+/// records with a successful status.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HtmlPages;
+impl PageSelector for HtmlPages {
+    fn is_page(&self, record: &IndexRecord) -> bool {
+        return record.mime_type.essence() == "text/html"
+            && (200..=299).contains(&record.http_status_code);
+    }
+}
+
+/// A stricter [`PageSelector`] than [`HtmlPages`], which only picks out
+/// HTML captured over HTTP(S) from a GET request. Records with URLs such
+/// as `urn:text:...` are left out, and so are form submissions.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct HttpGetPages;
+impl PageSelector for HttpGetPages {
+    fn is_page(&self, record: &IndexRecord) -> bool {
+        return HtmlPages.is_page(record)
+            && record.url.starts_with("http")
+            && record.is_get_request();
+    }
+}
+
+/// A [`PageSelector`] made up of a list of media types, a range of
+/// status codes and, optionally, a list of hosts. The default is the
+/// same as [`HtmlPages`].
+///
+/// For example, to include PDFs and XHTML from a single site,
+/// leaving out redirects and form submissions:
+///
+/// ```
+/// use wacksy::indexer::PagePolicy;
+///
+/// let page_policy = PagePolicy {
+///     mime_types: vec![
+///         "text/html".to_owned(),
+///         "application/xhtml+xml".to_owned(),
+///         "application/pdf".to_owned(),
+///     ],
+///     hosts: vec!["example.com".to_owned()],
+///     get_requests_only: true,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct PagePolicy {
+    /// Media types, without parameters, such as `text/html`.
+    pub mime_types: Vec<String>,
+    pub status_codes: RangeInclusive<usize>,
+    /// If this isn't empty, only URLs on one of these hosts are pages.
+    /// Hosts are matched without their port, ignoring case.
+    pub hosts: Vec<String>,
+    /// Leave out records captured from a request other than a GET,
+    /// such as form submissions.
+    pub get_requests_only: bool,
+}
+impl Default for PagePolicy {
+    fn default() -> Self {
+        return Self {
+            mime_types: vec!["text/html".to_owned()],
+            status_codes: 200..=299,
+            hosts: Vec::new(),
+            get_requests_only: false,
+        };
+    }
+}
+impl PageSelector for PagePolicy {
+    fn is_page(&self, record: &IndexRecord) -> bool {
+        return self
+            .mime_types
+            .iter()
+            .any(|mime_type| return mime_type.eq_ignore_ascii_case(record.mime_type.essence()))
+            && self.status_codes.contains(&record.http_status_code)
+            && (!self.get_requests_only || record.is_get_request())
+            && (self.hosts.is_empty()
+                || url_host(&record.url).is_some_and(|url_host| {
+                    return self
+                        .hosts
+                        .iter()
+                        .any(|host| return host.eq_ignore_ascii_case(url_host));
+                }));
+    }
+}
+
+/// The host of an http(s) URL, without any user name or port.
+fn url_host(url: &str) -> Option<&str> {
+    let (scheme, rest) = url.split_once("://")?;
+    if !scheme.eq_ignore_ascii_case("http") && !scheme.eq_ignore_ascii_case("https") {
+        return None;
+    }
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host_and_port = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host_and_port)| return host_and_port);
+    let host = if host_and_port.starts_with('[') {
+        host_and_port
+            .split_once(']')
+            .map_or(host_and_port, |(host, _)| return host)
+            .trim_start_matches('[')
+    } else {
+        host_and_port
+            .split_once(':')
+            .map_or(host_and_port, |(host, _)| return host)
+    };
+    return Some(host.trim_end_matches('.')).filter(|host| return !host.is_empty());
+}
+
+/// The URLs a crawl started from. Pages captured from one of these are
+/// listed in `pages/pages.jsonl`, and the rest in `pages/extraPages.jsonl`.
+///
+/// URLs are matched without their fragment, and a URL with no path
+/// matches the same URL ending in `/`.
+#[derive(Debug, Clone, Default)]
+pub struct SeedUrls {
+    urls: HashSet<String>,
+}
+impl SeedUrls {
+    pub fn new<I, S>(seed_urls: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        return Self {
+            urls: seed_urls
+                .into_iter()
+                .map(|seed_url| return normalise_url(seed_url.as_ref()))
+                .collect(),
+        };
+    }
+
+    /// Whether a captured URL is one of the seeds.
+    #[must_use]
+    pub fn contains(&self, url: &str) -> bool {
+        return self.urls.contains(&normalise_url(url));
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        return self.urls.is_empty();
+    }
+}
+
+/// Drop the fragment of a URL, and give a URL with
+/// no path the root path, as browsers do.
+fn normalise_url(url: &str) -> String {
+    let url = url.trim();
+    let url = url
+        .split_once('#')
+        .map_or(url, |(before_fragment, _)| return before_fragment);
+    let has_path = url
+        .split_once("://")
+        .is_none_or(|(_, after_scheme)| return after_scheme.contains(['/', '?']));
+    if has_path {
+        return url.to_owned();
+    } else {
+        return format!("{url}/");
+    }
+}
+
+#[test]
+fn html_pages() {
+    use crate::indexer::MimeType;
+    use std::sync::Arc;
+
+    let mut record = IndexRecord::new(0, Arc::from("example.warc"));
+    record.url = "https://example.com/".to_owned();
+    record.mime_type = MimeType::parse("text/html");
+    record.http_status_code = 299;
+    assert!(HtmlPages.is_page(&record));
+    assert!(HttpGetPages.is_page(&record));
+
+    record.http_status_code = 301;
+    assert!(!HtmlPages.is_page(&record));
+    assert!(!HttpGetPages.is_page(&record));
+
+    // Only the stricter selector leaves out form
+    // submissions and records which aren't HTTP.
+    record.http_status_code = 200;
+    record.http_method = Some("POST".to_owned());
+    assert!(HtmlPages.is_page(&record));
+    assert!(!HttpGetPages.is_page(&record));
+
+    record.http_method = None;
+    record.url = "urn:text:https://example.com/".to_owned();
+    assert!(HtmlPages.is_page(&record));
+    assert!(!HttpGetPages.is_page(&record));
+}
+
+#[test]
+fn page_policy() {
+    use crate::indexer::MimeType;
+    use std::sync::Arc;
+
+    let mut record = IndexRecord::new(0, Arc::from("example.warc"));
+    record.url = "https://Example.com:8443/report.pdf".to_owned();
+    record.mime_type = MimeType::parse("application/pdf");
+    record.http_status_code = 200;
+
+    // The default is the same as HtmlPages.
+    assert!(!PagePolicy::default().is_page(&record));
+
+    let page_policy = PagePolicy {
+        mime_types: vec!["text/html".to_owned(), "application/pdf".to_owned()],
+        status_codes: 200..=200,
+        hosts: vec!["example.com".to_owned()],
+        get_requests_only: true,
+    };
+    assert!(page_policy.is_page(&record));
+
+    record.http_status_code = 206;
+    assert!(!page_policy.is_page(&record));
+
+    record.http_status_code = 200;
+    record.http_method = Some("POST".to_owned());
+    assert!(!page_policy.is_page(&record));
+
+    record.http_method = None;
+    for url in [
+        "https://www.example.com/report.pdf",
+        "https://example.com.evil.net/",
+        "https://example.com@evil.net/",
+        "urn:text:https://example.com/",
+    ] {
+        record.url = url.to_owned();
+        assert!(
+            !page_policy.is_page(&record),
+            "{url} should be off the site"
+        );
+    }
+    record.url = "http://user@example.com./report.pdf".to_owned();
+    assert!(page_policy.is_page(&record));
+}
+
+#[test]
+fn seed_urls() {
+    let seed_urls = SeedUrls::new(["https://example.com", "https://example.com/about#team"]);
+    assert!(seed_urls.contains("https://example.com/"));
+    assert!(seed_urls.contains("https://example.com/#top"));
+    assert!(seed_urls.contains("https://example.com/about"));
+    assert!(!seed_urls.contains("https://example.com/about/"));
+    assert!(!seed_urls.contains("https://example.com/?page=2"));
+    assert!(SeedUrls::default().is_empty());
+}
@@ -0,0 +1,283 @@
+// Canonicalises request bodies the same way as pywb, see
+// https://github.com/webrecorder/pywb/blob/main/pywb/warcserver/inputrequest.py
+use serde_json::Value;
+use std::{collections::HashMap, fmt::Write as _};
+
+use crate::indexer::mime::MimeType;
+
+/// pywb only looks at this many bytes of a request body.
+pub const MAX_BODY_SIZE: usize = 16_384;
+
+/// Turn a request body into a query string, which can be added to the
+/// URL of a capture to tell apart requests to the same URL.
+///
+/// Form data and JSON bodies are turned into `key=value` pairs, anything
+/// else is base64 encoded into a single `__wb_post_data` parameter.
+pub fn canonicalise_body(mime_type: &MimeType, body: &[u8]) -> String {
+    let query = match mime_type.essence() {
+        "application/x-www-form-urlencoded" => form_query(body),
+        "multipart/form-data" => multipart_query(mime_type, body),
+        "application/json" | "text/plain" => json_query(body),
+        _ => None,
+    };
+    return query.unwrap_or_else(|| return binary_query(body));
+}
+
+/// The query which is added to the URL of a non-GET capture, made
+/// up of the method and the canonicalised request body. Spaces and
+/// other characters which can't appear in a CDXJ key are escaped.
+pub fn method_query(method: &str, request_body: Option<&str>) -> String {
+    let mut query = format!("__wb_method={}", method.to_ascii_lowercase());
+    if let Some(request_body) = request_body.filter(|request_body| return !request_body.is_empty())
+    {
+        query.push('&');
+        for character in request_body.chars() {
+            if character.is_ascii_whitespace() || character.is_ascii_control() || character == '#' {
+                let _ = write!(query, "%{:02X}", u32::from(character));
+            } else {
+                query.push(character);
+            }
+        }
+    }
+    return query;
+}
+
+/// Add a query string on to the end of a URL,
+/// which may already have a query of its own.
+pub fn append_query(url: &str, query: &str) -> String {
+    let separator = if url.contains('?') { '&' } else { '?' };
+    return format!("{url}{separator}{query}");
+}
+
+/// Form data is already a query string, but each key and value is
+/// decoded and encoded again so that it's stored the same way as the
+/// other formats.
+fn form_query(body: &[u8]) -> Option<String> {
+    let mut pairs = Vec::new();
+    for pair in body.split(|&byte| return byte == b'&') {
+        let mut key_value = pair.splitn(2, |&byte| return byte == b'=');
+        let key = String::from_utf8(percent_decode(key_value.next().unwrap_or_default())).ok()?;
+        let value = String::from_utf8(percent_decode(key_value.next().unwrap_or_default())).ok()?;
+        pairs.push(urlencode_pair(&key, &value));
+    }
+    return Some(pairs.join("&"));
+}
+
+/// Only the named text fields of a multipart form are kept,
+/// file uploads are left out.
+fn multipart_query(mime_type: &MimeType, body: &[u8]) -> Option<String> {
+    let boundary = mime_type.parameter("boundary")?;
+    let body = std::str::from_utf8(body).ok()?;
+
+    let mut fields = Vec::new();
+    for part in body.split(&format!("--{boundary}")) {
+        let Some((part_headers, part_value)) = part.split_once("\r\n\r\n") else {
+            continue;
+        };
+        let Some(disposition) = part_headers.lines().find(|header_line| {
+            return header_line
+                .to_ascii_lowercase()
+                .starts_with("content-disposition");
+        }) else {
+            continue;
+        };
+        if disposition.contains("filename=") {
+            continue;
+        }
+        let Some(name) = disposition
+            .split(';')
+            .find_map(|parameter| return parameter.trim().strip_prefix("name="))
+        else {
+            continue;
+        };
+        fields.push(urlencode_pair(
+            name.trim_matches('"'),
+            part_value.trim_end_matches("\r\n"),
+        ));
+    }
+    return Some(fields.join("&"));
+}
+
+/// JSON bodies are flattened into a `key=value` pair for each value,
+/// keys which appear more than once are numbered as `key.2_`, `key.3_`
+/// and so on. Keys stay in the order they appear in the body, which is
+/// why `serde_json` is used with the `preserve_order` feature.
+fn json_query(body: &[u8]) -> Option<String> {
+    fn flatten(
+        value: &Value,
+        name: &str,
+        pairs: &mut Vec<String>,
+        key_counts: &mut HashMap<String, usize>,
+    ) {
+        match value {
+            Value::Object(object) => {
+                for (key, value) in object {
+                    flatten(value, key, pairs, key_counts);
+                }
+            }
+            Value::Array(array) => {
+                for value in array {
+                    flatten(value, name, pairs, key_counts);
+                }
+            }
+            _ if name.is_empty() => {}
+            _ => {
+                let key_count = key_counts.entry(name.to_owned()).or_insert(0);
+                *key_count += 1;
+                let key = if *key_count == 1 {
+                    name.to_owned()
+                } else {
+                    format!("{name}.{key_count}_")
+                };
+                match value {
+                    Value::String(string) => pairs.push(urlencode_pair(&key, string)),
+                    _ => pairs.push(urlencode_pair(&key, &value.to_string())),
+                }
+            }
+        }
+    }
+
+    let value: Value = serde_json::from_slice(body).ok()?;
+    let mut pairs = Vec::new();
+    flatten(&value, "", &mut pairs, &mut HashMap::new());
+    return Some(pairs.join("&"));
+}
+
+/// Write a `key=value` pair the way Python's `urlencode` does, as pywb
+/// does for form, JSON and multipart bodies. Without this, a value
+/// holding `&` or `=` would be read back as more than one pair.
+fn urlencode_pair(key: &str, value: &str) -> String {
+    fn quote_plus(input: &str, quoted: &mut String) {
+        for byte in input.bytes() {
+            match byte {
+                b' ' => quoted.push('+'),
+                b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'_' | b'.' | b'-' | b'~' => {
+                    quoted.push(char::from(byte));
+                }
+                _ => {
+                    let _ = write!(quoted, "%{byte:02X}");
+                }
+            }
+        }
+    }
+
+    let mut pair = String::with_capacity(key.len() + value.len() + 1);
+    quote_plus(key, &mut pair);
+    pair.push('=');
+    quote_plus(value, &mut pair);
+    return pair;
+}
+
+fn binary_query(body: &[u8]) -> String {
+    return format!("__wb_post_data={}", base64_encode(body));
+}
+
+/// Decode `%XX` escapes, and `+` as a space.
+fn percent_decode(input: &[u8]) -> Vec<u8> {
+    fn hex_value(byte: u8) -> Option<u8> {
+        return (byte as char)
+            .to_digit(16)
+            .and_then(|digit| return u8::try_from(digit).ok());
+    }
+
+    let mut decoded = Vec::with_capacity(input.len());
+    let mut position = 0;
+    while position < input.len() {
+        match input[position] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                match (
+                    input.get(position + 1).copied().and_then(hex_value),
+                    input.get(position + 2).copied().and_then(hex_value),
+                ) {
+                    (Some(high), Some(low)) => {
+                        decoded.push(high * 16 + low);
+                        position += 2;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        position += 1;
+    }
+    return decoded;
+}
+
+/// Standard base64, with padding.
+fn base64_encode(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let bytes = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let triple = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for index in 0..4 {
+            if index <= chunk.len() {
+                let sextet = (triple >> (18 - 6 * index)) & 0x3f;
+                encoded.push(char::from(ALPHABET[sextet as usize]));
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    return encoded;
+}
+
+#[test]
+fn canonical_request_bodies() {
+    let test_cases: [(&str, &[u8], &str); 8] = [
+        (
+            "application/x-www-form-urlencoded",
+            b"q=web+archive&lang=en%2Dgb&and=a%26b",
+            "q=web+archive&lang=en-gb&and=a%26b",
+        ),
+        (
+            "application/json; charset=utf-8",
+            br#"{"a": 1, "b": {"c": "two", "a": [true, null]}}"#,
+            "a=1&c=two&a.2_=true&a.3_=null",
+        ),
+        ("text/plain", br#"{"query": "x"}"#, "query=x"),
+        // Values are urlencoded, so they can't be mistaken for more pairs.
+        (
+            "application/json",
+            br#"{"q": "a&b=c d", "n": "caf\u00e9"}"#,
+            "q=a%26b%3Dc+d&n=caf%C3%A9",
+        ),
+        ("text/plain", b"not json", "__wb_post_data=bm90IGpzb24="),
+        ("application/octet-stream", b"\x00\x01", "__wb_post_data=AAE="),
+        (
+            "multipart/form-data; boundary=XyZ",
+            b"--XyZ\r\nContent-Disposition: form-data; name=\"title\"\r\n\r\nHello\r\n--XyZ\r\nContent-Disposition: form-data; name=\"upload\"; filename=\"a.txt\"\r\n\r\nfile\r\n--XyZ--\r\n",
+            "title=Hello",
+        ),
+        ("", b"abc", "__wb_post_data=YWJj"),
+    ];
+
+    for test_case in test_cases {
+        assert_eq!(
+            canonicalise_body(&MimeType::parse(test_case.0), test_case.1),
+            test_case.2
+        );
+    }
+
+    assert_eq!(
+        append_query(
+            "https://example.com/search",
+            &method_query("POST", Some("q=1"))
+        ),
+        "https://example.com/search?__wb_method=post&q=1"
+    );
+    assert_eq!(
+        method_query("POST", Some("q=two words#1")),
+        "__wb_method=post&q=two%20words%231"
+    );
+    assert_eq!(
+        append_query("https://example.com/?a=b", &method_query("PUT", None)),
+        "https://example.com/?a=b&__wb_method=put"
+    );
+}
@@ -0,0 +1,177 @@
+// Sorts CDXJ lines, spilling to temporary files when there are too
+// many to sort in memory.
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead as _, BufReader, BufWriter, ErrorKind, Write},
+    mem,
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// Used to give each temporary file made by this process its own name.
+static TEMPORARY_FILE_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// Write out lines in byte order, separated by newlines. Lines are
+/// sorted in memory until they take up more than `max_memory` bytes,
+/// then each sorted run is written to a temporary file, and the runs
+/// are merged together at the end.
+///
+/// Comparing whole CDXJ lines as bytes sorts them by urlkey and then
+/// timestamp, as the space after the urlkey sorts before any character
+/// which can appear in one.
+pub fn write_sorted_lines<W: Write>(
+    lines: impl Iterator<Item = String>,
+    writer: &mut W,
+    max_memory: usize,
+) -> io::Result<()> {
+    let mut sorted_runs: Vec<TemporaryFile> = Vec::new();
+    let mut lines_in_memory: Vec<String> = Vec::new();
+    let mut memory_used = 0;
+
+    for line in lines {
+        memory_used += line.len() + mem::size_of::<String>();
+        lines_in_memory.push(line);
+        if memory_used > max_memory {
+            sorted_runs.push(write_sorted_run(&mut lines_in_memory)?);
+            memory_used = 0;
+        }
+    }
+
+    // Everything fitted in memory, so there's nothing to merge.
+    if sorted_runs.is_empty() {
+        lines_in_memory.sort_unstable();
+        return write_lines(lines_in_memory.into_iter().map(Ok), writer);
+    }
+    if !lines_in_memory.is_empty() {
+        sorted_runs.push(write_sorted_run(&mut lines_in_memory)?);
+    }
+    return merge_runs(&sorted_runs, writer);
+}
+
+/// Sort the lines, write them to a new temporary file, and empty the list.
+fn write_sorted_run(lines: &mut Vec<String>) -> io::Result<TemporaryFile> {
+    lines.sort_unstable();
+    let (temporary_file, file) = TemporaryFile::create()?;
+    let mut run_writer = BufWriter::new(file);
+    for line in lines.drain(..) {
+        run_writer.write_all(line.as_bytes())?;
+        run_writer.write_all(b"\n")?;
+    }
+    run_writer.flush()?;
+    return Ok(temporary_file);
+}
+
+/// Merge sorted runs together, by repeatedly taking the
+/// lowest of the next lines from each of them.
+fn merge_runs<W: Write>(sorted_runs: &[TemporaryFile], writer: &mut W) -> io::Result<()> {
+    let mut run_readers = sorted_runs
+        .iter()
+        .map(|sorted_run| return File::open(&sorted_run.path).map(BufReader::new))
+        .collect::<io::Result<Vec<BufReader<File>>>>()?;
+
+    let mut next_lines: BinaryHeap<Reverse<(String, usize)>> =
+        BinaryHeap::with_capacity(run_readers.len());
+    for (run_number, run_reader) in run_readers.iter_mut().enumerate() {
+        if let Some(line) = read_line(run_reader)? {
+            next_lines.push(Reverse((line, run_number)));
+        }
+    }
+
+    let merged_lines = std::iter::from_fn(|| {
+        let Reverse((line, run_number)) = next_lines.pop()?;
+        return match read_line(&mut run_readers[run_number]) {
+            Ok(Some(next_line)) => {
+                next_lines.push(Reverse((next_line, run_number)));
+                Some(Ok(line))
+            }
+            Ok(None) => Some(Ok(line)),
+            Err(error) => Some(Err(error)),
+        };
+    });
+    return write_lines(merged_lines, writer);
+}
+
+/// Read the next line of a sorted run, without its newline.
+fn read_line(run_reader: &mut BufReader<File>) -> io::Result<Option<String>> {
+    let mut line = String::new();
+    if run_reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    line.truncate(line.trim_end_matches('\n').len());
+    return Ok(Some(line));
+}
+
+/// Write lines separated by newlines, with no newline after the last one.
+fn write_lines<W: Write>(
+    lines: impl Iterator<Item = io::Result<String>>,
+    writer: &mut W,
+) -> io::Result<()> {
+    for (line_number, line) in lines.enumerate() {
+        if line_number > 0 {
+            writer.write_all(b"\n")?;
+        }
+        writer.write_all(line?.as_bytes())?;
+    }
+    return Ok(());
+}
+
+/// A file in the system's temporary directory,
+/// which is deleted when this is dropped.
+struct TemporaryFile {
+    path: PathBuf,
+}
+impl TemporaryFile {
+    fn create() -> io::Result<(Self, File)> {
+        loop {
+            let file_number = TEMPORARY_FILE_COUNT.fetch_add(1, Ordering::Relaxed);
+            let path = env::temp_dir().join(format!("wacksy-{}-{file_number}.cdxj", process::id()));
+            // A file could be left over from an earlier process with the
+            // same id, so don't overwrite it, just try the next name.
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((Self { path }, file)),
+                Err(error) if error.kind() == ErrorKind::AlreadyExists => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
+}
+impl Drop for TemporaryFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[test]
+fn sorted_lines() {
+    let lines = [
+        "org,example)/b 20250101000000 {}",
+        "org,example)/a/b 20250101000000 {}",
+        "org,example)/a 20250102000000 {}",
+        "com,example)/ 20250101000000 {}",
+        "org,example)/a 20250101000000 {}",
+    ];
+    let expected = [
+        "com,example)/ 20250101000000 {}",
+        "org,example)/a 20250101000000 {}",
+        "org,example)/a 20250102000000 {}",
+        "org,example)/a/b 20250101000000 {}",
+        "org,example)/b 20250101000000 {}",
+    ]
+    .join("\n");
+
+    // However little memory there is, the output is the same.
+    for max_memory in [usize::MAX, 100, 0] {
+        let mut sorted = Vec::new();
+        write_sorted_lines(
+            lines.iter().map(|line| return (*line).to_owned()),
+            &mut sorted,
+            max_memory,
+        )
+        .unwrap();
+        assert_eq!(String::from_utf8(sorted).unwrap(), expected);
+    }
+}
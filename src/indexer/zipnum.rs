@@ -0,0 +1,117 @@
+// Writes a sorted CDXJ index as a ZipNum index, which is split into
+// blocks of lines that are gzipped separately, along with a secondary
+// index of where each block starts. Replay tools look up the block
+// they need in the secondary index, then fetch just that block.
+use base16ct::HexDisplay;
+use flate2::{Compression, write::GzEncoder};
+use serde_json::json;
+use sha2::{Digest as _, Sha256};
+use std::io::{self, Write as _};
+
+/// The name of the file holding the gzipped blocks, which
+/// each line of the secondary index points to.
+pub const COMPRESSED_INDEX_FILE_NAME: &str = "index.cdx.gz";
+
+/// The name of the secondary index file.
+pub const SECONDARY_INDEX_FILE_NAME: &str = "index.idx";
+
+/// A CDXJ index split into gzipped blocks, for `indexes/index.cdx.gz`,
+/// with the secondary index for `indexes/index.idx`.
+#[derive(Debug)]
+pub struct ZipNumIndex {
+    /// The gzipped blocks, one after another.
+    pub compressed_index: Vec<u8>,
+    /// A line of metadata, then one line for each block, with the
+    /// urlkey and timestamp of its first line and where to find it
+    /// in the compressed index.
+    pub secondary_index: String,
+}
+
+/// Split the lines of a sorted CDXJ index into blocks of `block_size`
+/// lines, and gzip each one separately.
+pub fn write_zipnum(cdxj_index: &[u8], block_size: usize) -> io::Result<ZipNumIndex> {
+    let cdxj_lines: Vec<&[u8]> = cdxj_index
+        .split(|byte| return *byte == b'\n')
+        .filter(|cdxj_line| return !cdxj_line.is_empty())
+        .collect();
+
+    let mut compressed_index = Vec::with_capacity(cdxj_index.len() / 4);
+    let mut secondary_lines = vec![format!(
+        "!meta 0 {}",
+        json!({
+            "format": "cdxj-gzip-1.0",
+            "filename": COMPRESSED_INDEX_FILE_NAME,
+        })
+    )];
+
+    for block in cdxj_lines.chunks(block_size.max(1)) {
+        let offset = compressed_index.len();
+        let mut encoder = GzEncoder::new(&mut compressed_index, Compression::default());
+        for cdxj_line in block {
+            encoder.write_all(cdxj_line)?;
+            encoder.write_all(b"\n")?;
+        }
+        encoder.finish()?;
+        let compressed_block = &compressed_index[offset..];
+
+        // Blocks are found by the urlkey and timestamp of their first line.
+        let block_key = block[0]
+            .splitn(3, |byte| return *byte == b' ')
+            .take(2)
+            .map(String::from_utf8_lossy)
+            .collect::<Vec<_>>()
+            .join(" ");
+        let block_location = json!({
+            "offset": offset,
+            "length": compressed_block.len(),
+            "digest": format!("sha256:{:x}", HexDisplay(&Sha256::digest(compressed_block))),
+            "filename": COMPRESSED_INDEX_FILE_NAME,
+        });
+        secondary_lines.push(format!("{block_key} {block_location}"));
+    }
+
+    return Ok(ZipNumIndex {
+        compressed_index,
+        secondary_index: secondary_lines.join("\n"),
+    });
+}
+
+#[test]
+fn zipnum_blocks() {
+    use flate2::read::GzDecoder;
+    use serde_json::Value;
+    use std::io::Read as _;
+
+    let cdxj_index = [
+        "com,example)/ 20250101000000 {\"url\":\"https://example.com/\"}",
+        "com,example)/a 20250101000000 {\"url\":\"https://example.com/a\"}",
+        "com,example)/b 20250101000000 {\"url\":\"https://example.com/b\"}",
+    ]
+    .join("\n");
+    let zipnum_index = write_zipnum(cdxj_index.as_bytes(), 2).unwrap();
+
+    let secondary_lines: Vec<&str> = zipnum_index.secondary_index.lines().collect();
+    assert_eq!(
+        secondary_lines[0],
+        r#"!meta 0 {"format":"cdxj-gzip-1.0","filename":"index.cdx.gz"}"#
+    );
+    assert_eq!(secondary_lines.len(), 3);
+    assert!(secondary_lines[2].starts_with("com,example)/b 20250101000000 {\"offset\":"));
+
+    // Each block can be read on its own.
+    let mut blocks = Vec::new();
+    for secondary_line in &secondary_lines[1..] {
+        let (_, block_location) = secondary_line.split_once(" {").unwrap();
+        let block_location: Value = serde_json::from_str(&format!("{{{block_location}")).unwrap();
+        let offset = usize::try_from(block_location["offset"].as_u64().unwrap()).unwrap();
+        let length = usize::try_from(block_location["length"].as_u64().unwrap()).unwrap();
+
+        let mut block = String::new();
+        GzDecoder::new(&zipnum_index.compressed_index[offset..offset + length])
+            .read_to_string(&mut block)
+            .unwrap();
+        blocks.push(block);
+    }
+    assert_eq!(blocks.concat().trim_end(), cdxj_index);
+    assert_eq!(blocks[1].lines().count(), 1);
+}
@@ -23,13 +23,20 @@
 
 pub mod datapackage;
 pub mod indexer;
-use std::{error::Error, fmt, path::Path};
+mod parallel;
+pub mod query;
+use std::{error::Error, fmt, path::Path, sync::Arc};
 
 use rawzip::{CompressionMethod, ZipArchiveWriter};
 
 use crate::{
     datapackage::{DataPackage, DataPackageDigest, DataPackageError},
-    indexer::{IndexRecord, indexer},
+    indexer::{
+        DEFAULT_MAX_SORT_MEMORY, DEFAULT_MAX_TEXT_SIZE, DEFAULT_ZIPNUM_BLOCK_SIZE, HtmlPages,
+        IndexRecord, IndexerError, IndexerOptions, PageSelector, SkippedRegion,
+        indexer_with_options,
+    },
+    parallel::parallel_map,
 };
 
 /// Set the WACZ version of the file being created,
@@ -40,11 +47,90 @@ pub const WACZ_VERSION: &str = "1.1.1";
 pub struct WACZ {
     pub datapackage: DataPackage,
     pub datapackage_digest: DataPackageDigest,
+    /// Regions of the WARC files which could not be indexed,
+    /// this is only ever filled in [lenient](WaczOptions::lenient) mode.
+    pub skipped_regions: Vec<SkippedRegion>,
+}
+
+/// Options for creating a WACZ with [`WACZ::from_files_with_options`].
+///
+/// ```
+/// # use wacksy::WaczOptions;
+/// let options = WaczOptions {
+///     lenient: true,
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone)]
+pub struct WaczOptions {
+    /// Skip over corrupt records in the WARC files and carry on indexing,
+    /// rather than returning an error. See [`lenient_indexer`](indexer::lenient_indexer).
+    pub lenient: bool,
+    /// The number of threads used to index and hash the WARC files, with
+    /// one file per thread at a time. The output is the same whatever
+    /// this is set to. `0` and `1` both mean that everything runs on the
+    /// calling thread, which is the default.
+    pub threads: usize,
+    /// Chooses which records are listed in `pages.jsonl`, with their
+    /// titles, the default is [`HtmlPages`].
+    pub page_selector: Arc<dyn PageSelector>,
+    /// The URLs the crawl started from. If any are given, the pages
+    /// captured from them are listed in `pages/pages.jsonl`, and all
+    /// the other pages in `pages/extraPages.jsonl`. Otherwise every
+    /// page is listed in `pages/pages.jsonl`. See [`SeedUrls`](indexer::SeedUrls).
+    pub seed_urls: Vec<String>,
+    /// The page a WACZ opens on in `ReplayWeb`. If this isn't set, it's
+    /// the first seed page in the index, or the first page if there are
+    /// no seeds.
+    pub main_page_url: Option<String>,
+    /// The capture date of the main page, in RFC 3339 format. If this
+    /// isn't set, it's the date of the first capture of the main page.
+    pub main_page_date: Option<String>,
+    /// Write the visible text of each page to `pages.jsonl`, which
+    /// `ReplayWeb` uses for full-text search. This makes indexing
+    /// slower, and the WACZ larger.
+    pub extract_text: bool,
+    /// The most text written for each page, in bytes, when
+    /// `extract_text` is set. Longer text is cut short.
+    pub max_text_size: usize,
+    /// How much memory the CDXJ index can take up while it's being
+    /// sorted, in bytes. Larger indexes are sorted in batches which are
+    /// written to temporary files, see [`write_cdxj`](indexer::write_cdxj).
+    pub max_sort_memory: usize,
+    /// Write the CDXJ index as a `ZipNum` index, `indexes/index.cdx.gz`
+    /// and `indexes/index.idx`, rather than `indexes/index.cdxj`. The
+    /// index is split into gzipped blocks, so `ReplayWeb` only has to
+    /// load the blocks it needs, which helps with large collections.
+    /// See [`to_zipnum_index`](indexer::to_zipnum_index).
+    pub zipnum_index: bool,
+    /// The number of CDXJ lines in each block, when `zipnum_index` is set.
+    pub zipnum_block_size: usize,
+}
+impl Default for WaczOptions {
+    fn default() -> Self {
+        return Self {
+            lenient: false,
+            threads: 0,
+            page_selector: Arc::new(HtmlPages),
+            seed_urls: Vec::new(),
+            main_page_url: None,
+            main_page_date: None,
+            extract_text: false,
+            max_text_size: DEFAULT_MAX_TEXT_SIZE,
+            max_sort_memory: DEFAULT_MAX_SORT_MEMORY,
+            zipnum_index: false,
+            zipnum_block_size: DEFAULT_ZIPNUM_BLOCK_SIZE,
+        };
+    }
 }
 impl WACZ {
     /// # Create WACZ from a single WARC file
     ///
     /// Wrapper around `from_files` for backwards compatability.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`WaczError`], see [`WACZ::from_files`].
     pub fn from_file(warc_file_path: &Path) -> Result<Self, WaczError> {
         return Self::from_files(&[warc_file_path]);
     }
@@ -58,10 +144,27 @@ impl WACZ {
     /// # Errors
     ///
     /// Returns a [`WaczError`], which can be caused by a problem in either the
-    /// indexer (structured errors to-do) or the [datapackage](DataPackageError).
+    /// [indexer](IndexerError) or the [datapackage](DataPackageError).
     /// As the datapackage depends on the index being complete, any problem
     /// with the indexer will return early without continuing.
     pub fn from_files(warc_file_paths: &[&Path]) -> Result<Self, WaczError> {
+        return Self::from_files_with_options(warc_file_paths, &WaczOptions::default());
+    }
+
+    /// # Create WACZ from one or more WARC files, with options
+    ///
+    /// Works the same as [`WACZ::from_files`], with [`WaczOptions`] to change
+    /// how the WACZ is created.
+    ///
+    /// # Errors
+    ///
+    /// Returns a [`WaczError`], see [`WACZ::from_files`]. In lenient mode,
+    /// corrupt records do not cause an error, and are listed in
+    /// `skipped_regions` instead.
+    pub fn from_files_with_options(
+        warc_file_paths: &[&Path],
+        options: &WaczOptions,
+    ) -> Result<Self, WaczError> {
         // Check that at least one WARC is provided
         if warc_file_paths.is_empty() {
             return Err(WaczError::WarcFileError("No file".to_string()));
@@ -72,30 +175,54 @@ impl WACZ {
         // error, to avoid TOCTU mistakes.
         let missing_paths: Vec<String> = warc_file_paths
             .iter()
-            .filter(|path| !path.exists())
-            .map(|path| path.to_string_lossy().to_string())
+            .filter(|path| return !path.exists())
+            .map(|path| return path.to_string_lossy().to_string())
             .collect();
         if !missing_paths.is_empty() {
             return Err(WaczError::WarcFileError(missing_paths.join(", ")));
         }
 
         // Generate WACZ
-        let index: Vec<IndexRecord> = warc_file_paths
-            .iter()
-            .flat_map(|path| indexer(path))
-            .collect();
+        let indexer_options = IndexerOptions {
+            lenient: options.lenient,
+            // Titles are always listed in pages.jsonl, but
+            // only the pages themselves need to be read.
+            extract_titles: true,
+            extract_text: options.extract_text,
+            max_text_size: options.max_text_size,
+            page_selector: Arc::clone(&options.page_selector),
+        };
+        let indexer_results = parallel_map(warc_file_paths, options.threads, |warc_file_path| {
+            return indexer_with_options(warc_file_path, &indexer_options);
+        });
 
-        let datapackage: DataPackage = match DataPackage::new(warc_file_paths, &index) {
-            Ok(datapackage) => datapackage,
-            Err(datapackage_error) => {
-                return Err(WaczError::DataPackageError(datapackage_error));
+        // Put the results together in the order the files were given, so
+        // that the first error is the same as when indexing one at a time.
+        let mut index: Vec<IndexRecord> = Vec::with_capacity(512);
+        let mut skipped_regions: Vec<SkippedRegion> = Vec::new();
+        for indexer_result in indexer_results {
+            match indexer_result {
+                Ok((file_index, file_skipped_regions)) => {
+                    index.extend(file_index);
+                    skipped_regions.extend(file_skipped_regions);
+                }
+                Err(indexer_error) => return Err(WaczError::IndexerError(indexer_error)),
             }
-        };
+        }
+
+        let datapackage: DataPackage =
+            match DataPackage::new_with_options(warc_file_paths, &index, options) {
+                Ok(datapackage) => datapackage,
+                Err(datapackage_error) => {
+                    return Err(WaczError::DataPackageError(datapackage_error));
+                }
+            };
         let datapackage_digest = datapackage.digest();
 
         return Ok(Self {
             datapackage,
             datapackage_digest,
+            skipped_regions,
         });
     }
 
@@ -185,6 +312,7 @@ impl WACZ {
 #[derive(Debug)]
 pub enum WaczError {
     WarcFileError(String),
+    IndexerError(IndexerError),
     DataPackageError(DataPackageError),
 }
 impl fmt::Display for WaczError {
@@ -193,6 +321,9 @@ impl fmt::Display for WaczError {
             Self::WarcFileError(file_path) => {
                 return write!(message, "No file found at {file_path}");
             }
+            Self::IndexerError(error_message) => {
+                return write!(message, "Error when indexing WARC: {error_message}");
+            }
             Self::DataPackageError(error_message) => {
                 return write!(message, "Error when creating datapackage: {error_message}");
             }
@@ -203,6 +334,7 @@ impl Error for WaczError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
             Self::WarcFileError(_) => return None,
+            Self::IndexerError(indexer_error) => return Some(indexer_error),
             Self::DataPackageError(datapackage_error) => return Some(datapackage_error),
         }
     }
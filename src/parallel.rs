@@ -0,0 +1,70 @@
+//! A small pool of scoped threads, used to index and hash
+//! several WARC files at the same time.
+
+use std::{
+    panic,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
+
+/// Apply a function to every item using up to `threads` threads, and
+/// return the results in the same order as the items.
+///
+/// Each thread takes the next item which hasn't been started yet, so
+/// a few large files don't hold up the rest. With fewer than two
+/// threads, or fewer than two items, everything runs on the
+/// calling thread.
+pub fn parallel_map<T, R, F>(items: &[T], threads: usize, function: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    if threads < 2 || items.len() < 2 {
+        return items.iter().map(function).collect();
+    }
+
+    let next_item = AtomicUsize::new(0);
+    let mut numbered_results: Vec<(usize, R)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(items.len()))
+            .map(|_| {
+                return scope.spawn(|| {
+                    let mut worker_results = Vec::new();
+                    loop {
+                        let item_number = next_item.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(item_number) else {
+                            return worker_results;
+                        };
+                        worker_results.push((item_number, function(item)));
+                    }
+                });
+            })
+            .collect();
+
+        // Pass a panic in any of the threads on to the caller.
+        return workers
+            .into_iter()
+            .flat_map(|worker| match worker.join() {
+                Ok(worker_results) => return worker_results,
+                Err(panic_payload) => panic::resume_unwind(panic_payload),
+            })
+            .collect();
+    });
+
+    numbered_results.sort_unstable_by_key(|(item_number, _)| return *item_number);
+    return numbered_results
+        .into_iter()
+        .map(|(_, result)| return result)
+        .collect();
+}
+
+#[test]
+fn results_keep_their_order() {
+    let items: Vec<u64> = (0..100).collect();
+    let square = |item: &u64| return item * item;
+
+    let sequential = parallel_map(&items, 1, square);
+    assert_eq!(sequential, parallel_map(&items, 4, square));
+    assert_eq!(sequential, parallel_map(&items, 200, square));
+    assert_eq!(sequential[99], 9801);
+}
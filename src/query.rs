@@ -0,0 +1,514 @@
+//! Looks up captures in a sorted CDXJ index.
+//!
+//! Works in the same way as the CDX server of pywb: the URL is turned
+//! into a SURT with [`create_surt`], and the lines of the index which
+//! could match are found by binary search, then filtered by timestamp.
+//!
+//! ```
+//! # use std::{error::Error, path::Path};
+//! # use wacksy::{indexer::indexer, query::{MatchType, QueryOptions, query_index}};
+//! # fn main() -> Result<(), Box<dyn Error>> {
+//! let index = indexer(Path::new("tests/example.warc.gz"))?;
+//! let options = QueryOptions {
+//!     match_type: MatchType::Domain,
+//!     from: Some("2025".to_owned()),
+//!     ..Default::default()
+//! };
+//! for capture in query_index(&index, "example.com", &options)? {
+//!     println!("{} {}", capture.timestamp, capture.url().unwrap_or_default());
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use chrono::NaiveDateTime;
+use flate2::read::GzDecoder;
+use serde_json::{Map, Value};
+use std::{error::Error, fmt, fs, io::Read as _, path::Path};
+
+use crate::{
+    WACZ,
+    indexer::{IndexRecord, SurtError, create_surt, to_cdxj_string},
+};
+
+/// How much of the URL has to match, as in the `matchType`
+/// parameter of pywb and the Wayback Machine.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum MatchType {
+    /// Captures of this URL only, which is the default.
+    #[default]
+    Exact,
+    /// Captures of any URL starting with this one,
+    /// so `example.com/a` matches `example.com/about`.
+    Prefix,
+    /// Captures of any URL on the same host.
+    Host,
+    /// Captures of any URL on the same host or its subdomains,
+    /// so `example.com` matches `www.example.com` and `blog.example.com`.
+    Domain,
+}
+
+/// Options for a query, with the same meaning as
+/// the parameters of the pywb CDX server.
+///
+/// Timestamps can be cut short, such as `2025` or `202506`. A
+/// shortened `from` timestamp means the start of that period, and
+/// a shortened `to` timestamp means the end of it.
+#[derive(Debug, Clone, Default)]
+pub struct QueryOptions {
+    pub match_type: MatchType,
+    /// Leave out captures made before this timestamp.
+    pub from: Option<String>,
+    /// Leave out captures made after this timestamp.
+    pub to: Option<String>,
+    /// Sort the captures by how close they are to this timestamp,
+    /// rather than by URL and then timestamp.
+    pub closest: Option<String>,
+    /// The most captures to return.
+    pub limit: Option<usize>,
+}
+
+/// A capture found by a query, which is one line of the CDXJ index.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capture {
+    /// The SURT of the URL.
+    pub urlkey: String,
+    /// The capture date, in the 14 digit `YYYYMMDDhhmmss` format.
+    pub timestamp: String,
+    /// The rest of the line, such as `url`, `digest` and `offset`.
+    pub fields: Map<String, Value>,
+}
+impl Capture {
+    /// The URL which was captured.
+    #[must_use]
+    pub fn url(&self) -> Option<&str> {
+        return self.fields.get("url").and_then(Value::as_str);
+    }
+}
+impl fmt::Display for Capture {
+    fn fmt(&self, message: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return write!(
+            message,
+            "{} {} {}",
+            self.urlkey,
+            self.timestamp,
+            Value::Object(self.fields.clone())
+        );
+    }
+}
+
+/// # Query a sorted CDXJ index
+///
+/// The index has to be sorted by urlkey and then timestamp, as
+/// [`write_cdxj`](crate::indexer::write_cdxj) writes it. Captures are
+/// returned in the same order, unless the `closest` option is set.
+///
+/// # Errors
+///
+/// Returns a [`QueryError`] if the URL can't be turned into a SURT,
+/// if a timestamp option isn't made up of digits, or if a matching
+/// line of the index isn't valid CDXJ.
+pub fn query_cdxj(
+    cdxj_index: &[u8],
+    url: &str,
+    options: &QueryOptions,
+) -> Result<Vec<Capture>, QueryError> {
+    let (start_key, end_key) = search_range(url, options.match_type)?;
+    let from = options
+        .from
+        .as_deref()
+        .map(|from| return pad_timestamp(from, "10000101000000"))
+        .transpose()?;
+    let to = options
+        .to
+        .as_deref()
+        .map(|to| return pad_timestamp(to, "29991231235959"))
+        .transpose()?;
+    let closest = options
+        .closest
+        .as_deref()
+        .map(|closest| return parse_timestamp(&pad_timestamp(closest, "10000101000000")?))
+        .transpose()?;
+
+    let mut captures = Vec::new();
+    let first_line = first_line_from(cdxj_index, start_key.as_bytes());
+    for cdxj_line in cdxj_index[first_line..].split(|byte| return *byte == b'\n') {
+        if cdxj_line >= end_key.as_bytes() {
+            break;
+        }
+        if cdxj_line.is_empty() {
+            continue;
+        }
+        let capture = parse_line(cdxj_line)?;
+        if from
+            .as_ref()
+            .is_some_and(|from| return capture.timestamp < *from)
+            || to.as_ref().is_some_and(|to| return capture.timestamp > *to)
+        {
+            continue;
+        }
+        captures.push(capture);
+    }
+
+    if let Some(closest) = closest {
+        // Captures with a timestamp which can't be read go last.
+        captures.sort_by_key(|capture| {
+            return parse_timestamp(&capture.timestamp).map_or(i64::MAX, |timestamp| {
+                return (timestamp - closest).num_seconds().abs();
+            });
+        });
+    }
+    if let Some(limit) = options.limit {
+        captures.truncate(limit);
+    }
+    return Ok(captures);
+}
+
+/// # Query an index
+///
+/// Writes the records out as a sorted CDXJ index, then queries it.
+/// See [`query_cdxj`].
+///
+/// # Errors
+///
+/// See [`query_cdxj`].
+///
+/// # Panics
+///
+/// Will panic if a record timestamp is not valid RFC 3339,
+/// see [`to_cdxj_string`].
+pub fn query_index(
+    index: &[IndexRecord],
+    url: &str,
+    options: &QueryOptions,
+) -> Result<Vec<Capture>, QueryError> {
+    return query_cdxj(to_cdxj_string(index).as_bytes(), url, options);
+}
+
+/// # Query a CDXJ file
+///
+/// The file has to be sorted, see [`query_cdxj`].
+///
+/// # Errors
+///
+/// Returns a [`QueryError`] if the file can't be read, or for any
+/// of the reasons given by [`query_cdxj`].
+pub fn query_cdxj_file(
+    path: &Path,
+    url: &str,
+    options: &QueryOptions,
+) -> Result<Vec<Capture>, QueryError> {
+    let cdxj_index = match fs::read(path) {
+        Ok(cdxj_index) => cdxj_index,
+        Err(error) => return Err(QueryError::ReadError(error)),
+    };
+    return query_cdxj(&cdxj_index, url, options);
+}
+
+/// # Query the index of a WACZ
+///
+/// Uses `indexes/index.cdxj`, or for a WACZ with a
+/// [`ZipNum`](crate::WaczOptions::zipnum_index) index, only the
+/// blocks of `indexes/index.cdx.gz` which could hold matches.
+///
+/// # Errors
+///
+/// Returns a [`QueryError`] if the WACZ has no index, if a block of
+/// the index can't be decompressed, or for any of the reasons
+/// given by [`query_cdxj`].
+pub fn query_wacz(
+    wacz_object: &WACZ,
+    url: &str,
+    options: &QueryOptions,
+) -> Result<Vec<Capture>, QueryError> {
+    let find_resource = |path: &str| {
+        return wacz_object
+            .datapackage
+            .resources
+            .iter()
+            .find(|resource| return resource.path == path)
+            .map(|resource| return resource.content.as_slice());
+    };
+
+    if let Some(cdxj_index) = find_resource("indexes/index.cdxj") {
+        return query_cdxj(cdxj_index, url, options);
+    }
+    let (Some(compressed_index), Some(secondary_index)) = (
+        find_resource("indexes/index.cdx.gz"),
+        find_resource("indexes/index.idx"),
+    ) else {
+        return Err(QueryError::MissingIndex);
+    };
+
+    let (start_key, end_key) = search_range(url, options.match_type)?;
+    let cdxj_index = read_zipnum_blocks(compressed_index, secondary_index, &start_key, &end_key)?;
+    return query_cdxj(&cdxj_index, url, options);
+}
+
+/// Decompress the blocks of a `ZipNum` index which could hold
+/// lines from `start_key` up to `end_key`.
+fn read_zipnum_blocks(
+    compressed_index: &[u8],
+    secondary_index: &[u8],
+    start_key: &str,
+    end_key: &str,
+) -> Result<Vec<u8>, QueryError> {
+    // Each line of the secondary index is the key of the first line of
+    // a block, then a JSON block saying where the block is. The first
+    // line is metadata, which starts with `!`.
+    let mut blocks = Vec::new();
+    for secondary_line in String::from_utf8_lossy(secondary_index)
+        .lines()
+        .filter(|secondary_line| return !secondary_line.starts_with('!'))
+    {
+        let invalid_line = || return QueryError::InvalidLine(secondary_line.to_owned());
+        let (block_key, block_location) =
+            secondary_line.split_once(" {").ok_or_else(invalid_line)?;
+        let block_location: Value = serde_json::from_str(&format!("{{{block_location}"))
+            .map_err(|_| return invalid_line())?;
+        let offset = block_location["offset"]
+            .as_u64()
+            .and_then(|offset| return usize::try_from(offset).ok());
+        let length = block_location["length"]
+            .as_u64()
+            .and_then(|length| return usize::try_from(length).ok());
+        let (Some(offset), Some(length)) = (offset, length) else {
+            return Err(invalid_line());
+        };
+        blocks.push((block_key.to_owned(), offset, length));
+    }
+
+    // Matches can start part way through the block before the first
+    // one whose key comes after the start of the range.
+    let first_block = blocks
+        .partition_point(|(block_key, ..)| return block_key.as_str() < start_key)
+        .saturating_sub(1);
+    let last_block = blocks.partition_point(|(block_key, ..)| return block_key.as_str() < end_key);
+
+    let mut cdxj_index = Vec::new();
+    for (_, offset, length) in blocks.get(first_block..last_block).unwrap_or_default() {
+        let Some(block) = offset
+            .checked_add(*length)
+            .and_then(|block_end| return compressed_index.get(*offset..block_end))
+        else {
+            return Err(QueryError::InvalidLine(format!(
+                "block at {offset} is out of range"
+            )));
+        };
+        if let Err(error) = GzDecoder::new(block).read_to_end(&mut cdxj_index) {
+            return Err(QueryError::ReadError(error));
+        }
+    }
+    return Ok(cdxj_index);
+}
+
+/// The first and last keys a matching line can start with, as in
+/// `calc_search_range` in pywb. Matching lines sort from the first
+/// key, up to but not including the last key.
+fn search_range(url: &str, match_type: MatchType) -> Result<(String, String), QueryError> {
+    let surt = match create_surt(url) {
+        Ok(surt) => surt,
+        Err(error) => return Err(QueryError::InvalidUrl(error)),
+    };
+    let host = surt.split(")/").next().unwrap_or_default();
+
+    return Ok(match match_type {
+        // A space comes right after the urlkey, and sorts before `!`.
+        MatchType::Exact => (format!("{surt} "), format!("{surt}!")),
+        MatchType::Prefix => {
+            // The SURT drops any trailing slash, but here it's meaningful.
+            let mut start_key = surt;
+            if url.ends_with('/') && !start_key.ends_with('/') {
+                start_key.push('/');
+            }
+            let end_key = increment_last_character(&start_key);
+            (start_key, end_key)
+        }
+        MatchType::Host => (format!("{host})/"), format!("{host}*")),
+        // Subdomains come after the host and a comma, and `-` comes
+        // right after the comma.
+        MatchType::Domain => (format!("{host})/"), format!("{host}-")),
+    });
+}
+
+/// The smallest key which comes after every key starting with this one.
+fn increment_last_character(key: &str) -> String {
+    let mut characters: Vec<char> = key.chars().collect();
+    if let Some(last_character) = characters.pop() {
+        characters.push(char::from_u32(u32::from(last_character) + 1).unwrap_or(char::MAX));
+    }
+    return characters.into_iter().collect();
+}
+
+/// The byte offset of the first line of the sorted index which
+/// doesn't sort before `key`, found by binary search.
+fn first_line_from(cdxj_index: &[u8], key: &[u8]) -> usize {
+    // Lines before `low` sort before the key, and lines
+    // from `high` onwards don't. Both are always line starts.
+    let mut low = 0;
+    let mut high = cdxj_index.len();
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let line_start = cdxj_index[low..middle]
+            .iter()
+            .rposition(|byte| return *byte == b'\n')
+            .map_or(low, |newline| return low + newline + 1);
+        let line_end = cdxj_index[line_start..]
+            .iter()
+            .position(|byte| return *byte == b'\n')
+            .map_or(cdxj_index.len(), |newline| return line_start + newline);
+        if &cdxj_index[line_start..line_end] < key {
+            low = (line_end + 1).min(cdxj_index.len());
+        } else {
+            high = line_start;
+        }
+    }
+    return low;
+}
+
+/// Split a line of the index into its urlkey, timestamp and JSON block.
+fn parse_line(cdxj_line: &[u8]) -> Result<Capture, QueryError> {
+    let cdxj_line = String::from_utf8_lossy(cdxj_line);
+    let mut line_parts = cdxj_line.splitn(3, ' ');
+    if let (Some(urlkey), Some(timestamp), Some(json_block)) =
+        (line_parts.next(), line_parts.next(), line_parts.next())
+    {
+        if let Ok(fields) = serde_json::from_str(json_block) {
+            return Ok(Capture {
+                urlkey: urlkey.to_owned(),
+                timestamp: timestamp.to_owned(),
+                fields,
+            });
+        }
+    }
+    return Err(QueryError::InvalidLine(cdxj_line.into_owned()));
+}
+
+/// Fill in the end of a shortened timestamp from `padding`.
+fn pad_timestamp(timestamp: &str, padding: &str) -> Result<String, QueryError> {
+    if timestamp.is_empty()
+        || timestamp.len() > padding.len()
+        || !timestamp.bytes().all(|byte| return byte.is_ascii_digit())
+    {
+        return Err(QueryError::InvalidTimestamp(timestamp.to_owned()));
+    }
+    return Ok(format!("{timestamp}{}", &padding[timestamp.len()..]));
+}
+
+fn parse_timestamp(timestamp: &str) -> Result<NaiveDateTime, QueryError> {
+    return NaiveDateTime::parse_from_str(timestamp, "%Y%m%d%H%M%S")
+        .map_err(|_| return QueryError::InvalidTimestamp(timestamp.to_owned()));
+}
+
+/// The reason a query could not be run.
+#[derive(Debug)]
+pub enum QueryError {
+    /// The URL could not be turned into a SURT.
+    InvalidUrl(SurtError),
+    /// A timestamp option was not up to 14 digits, or not a real date.
+    InvalidTimestamp(String),
+    /// A line of the index was not valid CDXJ.
+    InvalidLine(String),
+    /// The WACZ had no CDXJ index.
+    MissingIndex,
+    /// The index could not be read or decompressed.
+    ReadError(std::io::Error),
+}
+impl fmt::Display for QueryError {
+    fn fmt(&self, message: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidUrl(error_message) => {
+                return write!(message, "Invalid URL: {error_message}");
+            }
+            Self::InvalidTimestamp(timestamp) => {
+                return write!(message, "Invalid timestamp \"{timestamp}\"");
+            }
+            Self::InvalidLine(cdxj_line) => {
+                return write!(message, "Invalid CDXJ line \"{cdxj_line}\"");
+            }
+            Self::MissingIndex => {
+                return write!(message, "The WACZ has no CDXJ index");
+            }
+            Self::ReadError(error_message) => {
+                return write!(message, "Could not read index: {error_message}");
+            }
+        }
+    }
+}
+impl Error for QueryError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::InvalidUrl(surt_error) => return Some(surt_error),
+            Self::ReadError(read_error) => return Some(read_error),
+            Self::InvalidTimestamp(_) | Self::InvalidLine(_) | Self::MissingIndex => return None,
+        }
+    }
+}
+
+#[test]
+fn search_ranges() {
+    let test_cases = [
+        (
+            "http://example.com/about",
+            MatchType::Exact,
+            "com,example)/about ",
+            "com,example)/about!",
+        ),
+        (
+            "http://example.com/a",
+            MatchType::Prefix,
+            "com,example)/a",
+            "com,example)/b",
+        ),
+        (
+            "http://example.com/dir/",
+            MatchType::Prefix,
+            "com,example)/dir/",
+            "com,example)/dir0",
+        ),
+        (
+            "http://www.example.com/page",
+            MatchType::Host,
+            "com,example)/",
+            "com,example*",
+        ),
+        (
+            "example.com",
+            MatchType::Domain,
+            "com,example)/",
+            "com,example-",
+        ),
+    ];
+    for (url, match_type, start_key, end_key) in test_cases {
+        assert_eq!(
+            search_range(url, match_type).unwrap(),
+            (start_key.to_owned(), end_key.to_owned())
+        );
+    }
+
+    let cdxj_index = b"a 1 {}\nb 1 {}\nb 2 {}\nc 1 {}";
+    assert_eq!(first_line_from(cdxj_index, b""), 0);
+    assert_eq!(first_line_from(cdxj_index, b"b "), 7);
+    assert_eq!(first_line_from(cdxj_index, b"b 2"), 14);
+    assert_eq!(first_line_from(cdxj_index, b"bb"), 21);
+    assert_eq!(first_line_from(cdxj_index, b"d"), cdxj_index.len());
+}
+
+#[test]
+fn zipnum_block_out_of_range() {
+    // A block whose end would overflow is out of range, not a panic.
+    let secondary_index = format!(
+        "com,example)/ 20250101000000 {{\"offset\":1,\"length\":{}}}",
+        usize::MAX
+    );
+    let result = read_zipnum_blocks(
+        b"",
+        secondary_index.as_bytes(),
+        "com,example)/ ",
+        "com,example)/!",
+    );
+    assert!(
+        matches!(result, Err(QueryError::InvalidLine(_))),
+        "expected an invalid line, got {result:?}"
+    );
+}
@@ -1,12 +1,22 @@
+use flate2::read::GzDecoder;
 use pretty_assertions::assert_eq;
-use std::{error::Error, fs, path::Path};
+use std::{collections::BTreeMap, error::Error, fs, io::Read as _, path::Path, sync::Arc};
 use wacksy::{
-    WACZ,
-    indexer::{indexer, to_cdxj_string},
+    WACZ, WaczError, WaczOptions,
+    indexer::{
+        HtmlPages, IndexRecord, IndexerError, IndexerOptions, PageSelector, RecordError,
+        create_surt, indexer, indexer_with_options, lenient_indexer, to_cdxj_string,
+        to_pages_json_string, to_pages_json_string_with_selector, write_cdxj,
+    },
+    query::{MatchType, QueryError, QueryOptions, query_cdxj_file, query_index, query_wacz},
 };
 
 const WARC_PATH: &str = "tests/example.warc.gz";
 const WARC_PATH_2: &str = "tests/example2.warc.gz";
+const WARC_1_0_PATH: &str = "tests/example-warc-1.0.warc.gz";
+const WARC_MALFORMED_PATH: &str = "tests/example-malformed.warc.gz";
+const WARC_CORRUPT_PATH: &str = "tests/example-corrupt.warc.gz";
+const WARC_CORRUPT_UNCOMPRESSED_PATH: &str = "tests/example-corrupt.warc";
 
 #[cfg(target_family = "windows")]
 fn normalize_newlines(s: String) -> String {
@@ -14,9 +24,14 @@ fn normalize_newlines(s: String) -> String {
 }
 
 #[test]
+#[allow(
+    clippy::implicit_return,
+    clippy::unnecessary_wraps,
+    clippy::needless_borrow
+)]
 fn create_cdxj_index() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
     let warc_file_path: &Path = Path::new(WARC_PATH);
-    let index = indexer(warc_file_path);
+    let index = indexer(warc_file_path)?;
     let generated_cdxj_index = to_cdxj_string(&index);
 
     #[cfg(target_family = "windows")]
@@ -31,8 +46,32 @@ fn create_cdxj_index() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
     Ok(())
 }
 
+#[test]
+fn create_pages_index() -> Result<(), Box<dyn Error>> {
+    let (index, _) = indexer_with_options(Path::new(WARC_PATH), &with_titles())?;
+    let generated_pages_index = to_pages_json_string(&index);
+    let example_pages_index = fs::read_to_string("tests/wacz_example/pages/pages.jsonl")?;
+    assert_eq!(generated_pages_index, example_pages_index);
+
+    // Every page matches the schema, and has its title.
+    let schema: serde_json::Value =
+        serde_json::from_reader(fs::File::open("tests/schemas/page-record.schema.json")?)?;
+    let validator = jsonschema::validator_for(&schema)?;
+    for page in example_pages_index.lines().skip(1) {
+        let page: serde_json::Value = serde_json::from_str(page)?;
+        assert!(validator.is_valid(&page));
+        assert_eq!(page["title"], "Example Domain");
+    }
+    return Ok(());
+}
+
 // test more error here?
 #[test]
+#[allow(
+    clippy::implicit_return,
+    clippy::unnecessary_wraps,
+    clippy::needless_borrow
+)]
 fn bad_file_path() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
     let warc_file_path: &Path = Path::new("bad/file/path");
     assert!(WACZ::from_file(warc_file_path).is_err());
@@ -40,6 +79,11 @@ fn bad_file_path() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
 }
 
 #[test]
+#[allow(
+    clippy::implicit_return,
+    clippy::unnecessary_wraps,
+    clippy::needless_borrow
+)]
 fn single_warc() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
     let warc_file_path: &Path = Path::new(WARC_PATH);
 
@@ -50,6 +94,11 @@ fn single_warc() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
 }
 
 #[test]
+#[allow(
+    clippy::implicit_return,
+    clippy::unnecessary_wraps,
+    clippy::needless_borrow
+)]
 fn single_warc_fromfile() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
     let warc_file_path: &Path = Path::new(WARC_PATH);
 
@@ -60,6 +109,11 @@ fn single_warc_fromfile() -> Result<(), Box<dyn Error + Send + Sync + 'static>>
 }
 
 #[test]
+#[allow(
+    clippy::implicit_return,
+    clippy::unnecessary_wraps,
+    clippy::needless_borrow
+)]
 fn multiple_warc() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
     let warc_file_path: &Path = Path::new(WARC_PATH);
     let warc_file_path2: &Path = Path::new(WARC_PATH_2);
@@ -69,3 +123,1356 @@ fn multiple_warc() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
 
     Ok(())
 }
+
+#[test]
+fn warc_1_0_index() {
+    let warc_file_path: &Path = Path::new(WARC_1_0_PATH);
+    let index = indexer(warc_file_path).unwrap();
+    let generated_cdxj_index = to_cdxj_string(&index);
+
+    // Target URIs in WARC/1.0 are wrapped in angle brackets,
+    // which should not end up in the index.
+    let expected_cdxj_index = [
+        r#"org,example,legacy)/ 20080610101535 {"url":"http://legacy.example.org/","digest":"sha1:4GFLX4NFHCAZGF3MJHJNNPEZ2BZ6PJ7M","mime":"text/html","offset":234,"length":158,"status":200,"filename":"example-warc-1.0.warc.gz"}"#,
+        r#"org,example,legacy)/logo.png 20080610101536 {"url":"http://legacy.example.org/logo.png","digest":"sha1:3I42H3S6NNFQ2MSVX7XZKYAYSCX5QBYJ","mime":"image/png","offset":826,"length":67,"status":200,"filename":"example-warc-1.0.warc.gz"}"#,
+    ]
+    .join("\n");
+
+    assert_eq!(generated_cdxj_index, expected_cdxj_index);
+}
+
+#[test]
+fn mixed_warc_versions() {
+    let warc_file_path: &Path = Path::new(WARC_PATH);
+    let warc_1_0_file_path: &Path = Path::new(WARC_1_0_PATH);
+
+    let index = [
+        indexer(warc_file_path).unwrap(),
+        indexer(warc_1_0_file_path).unwrap(),
+    ]
+    .concat();
+    assert_eq!(index.len(), 5);
+
+    let wacz_result = WACZ::from_files(&[warc_file_path, warc_1_0_file_path]);
+    assert!(wacz_result.is_ok());
+}
+
+#[test]
+fn malformed_record() {
+    let warc_file_path: &Path = Path::new(WARC_MALFORMED_PATH);
+
+    // The second record in the file has an unparseable content length,
+    // the error should say where it is.
+    match indexer(warc_file_path) {
+        Err(IndexerError::RecordError {
+            file_name,
+            offset,
+            reason: RecordError::InvalidHeaderValue { field, value },
+        }) => {
+            assert_eq!(file_name, "example-malformed.warc.gz");
+            assert_eq!(offset, 274);
+            assert_eq!(field, "Content-Length");
+            assert_eq!(value, "one hundred");
+        }
+        other => panic!("expected a record error, got {other:?}"),
+    }
+
+    // Creating a WACZ should return the error rather than panicking.
+    assert!(matches!(
+        WACZ::from_files(&[warc_file_path]),
+        Err(WaczError::IndexerError(_))
+    ));
+}
+
+#[test]
+fn oversized_content_length() {
+    // A Content-Length which can't be added to the offset of the record
+    // is an error for that record, rather than a panic.
+    let warc_file = [
+        "WARC/1.1\r\nWARC-Type: resource\r\nWARC-Target-URI: https://example.com/\r\nWARC-Date: 2025-09-01T00:00:00Z\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello\r\n\r\n",
+        "WARC/1.1\r\nWARC-Type: resource\r\nWARC-Target-URI: https://example.com/huge\r\nWARC-Date: 2025-09-01T00:00:00Z\r\nContent-Type: text/plain\r\nContent-Length: 18446744073709551615\r\n\r\nhello\r\n\r\n",
+    ]
+    .concat();
+    let warc_file_path = std::env::temp_dir().join("wacksy-oversized.warc");
+    fs::write(&warc_file_path, &warc_file).unwrap();
+    let result = indexer(&warc_file_path);
+    let lenient_result = lenient_indexer(&warc_file_path);
+    fs::remove_file(&warc_file_path).unwrap();
+
+    let second_record_offset = warc_file
+        .find("WARC/1.1\r\nWARC-Type: resource\r\nWARC-Target-URI: https://example.com/huge")
+        .unwrap();
+    match result {
+        Err(IndexerError::RecordError {
+            offset,
+            reason:
+                RecordError::ContentLengthOverflow {
+                    offset: record_offset,
+                    content_length,
+                },
+            ..
+        }) => {
+            assert_eq!(offset, second_record_offset);
+            assert_eq!(record_offset, second_record_offset);
+            assert_eq!(content_length, usize::MAX);
+        }
+        other => panic!("expected a record error, got {other:?}"),
+    }
+
+    // In lenient mode the rest of the file is skipped.
+    let (index, skipped_regions) = lenient_result.unwrap();
+    assert_eq!(index.len(), 1);
+    assert_eq!(skipped_regions.len(), 1);
+    assert_eq!(skipped_regions[0].offset, second_record_offset);
+}
+
+#[test]
+fn lenient_gzip() {
+    let warc_file_path: &Path = Path::new(WARC_CORRUPT_PATH);
+
+    // The file has junk bytes between the first and second records,
+    // and a truncated gzip member in place of the third.
+    assert!(indexer(warc_file_path).is_err());
+
+    let (index, skipped_regions) = lenient_indexer(warc_file_path).unwrap();
+    let urls: Vec<String> = to_cdxj_string(&index)
+        .lines()
+        .map(|line| return line.split(' ').next().unwrap().to_owned())
+        .collect();
+    assert_eq!(
+        urls,
+        [
+            "com,example,corrupt)/1",
+            "com,example,corrupt)/2",
+            "com,example,corrupt)/4"
+        ]
+    );
+
+    let skipped: Vec<(usize, usize)> = skipped_regions
+        .iter()
+        .map(|region| return (region.offset, region.length))
+        .collect();
+    assert_eq!(skipped, [(274, 27), (574, 60)]);
+    assert!(matches!(
+        skipped_regions[1].reason,
+        RecordError::ReadError(_)
+    ));
+}
+
+#[test]
+fn lenient_uncompressed() {
+    let warc_file_path: &Path = Path::new(WARC_CORRUPT_UNCOMPRESSED_PATH);
+
+    // The second record has a Content-Length which is too long,
+    // and there are junk bytes between the third and fourth records.
+    assert!(indexer(warc_file_path).is_err());
+
+    let (index, skipped_regions) = lenient_indexer(warc_file_path).unwrap();
+    let urls: Vec<String> = to_cdxj_string(&index)
+        .lines()
+        .map(|line| return line.split(' ').next().unwrap().to_owned())
+        .collect();
+    assert_eq!(
+        urls,
+        [
+            "com,example,corrupt)/1",
+            "com,example,corrupt)/3",
+            "com,example,corrupt)/4"
+        ]
+    );
+
+    let skipped: Vec<(usize, usize)> = skipped_regions
+        .iter()
+        .map(|region| return (region.offset, region.length))
+        .collect();
+    assert_eq!(skipped, [(458, 459), (1375, 27)]);
+    assert!(matches!(
+        skipped_regions[0].reason,
+        RecordError::InvalidRecordLength(_)
+    ));
+    assert!(matches!(
+        skipped_regions[1].reason,
+        RecordError::UnsupportedVersion(_)
+    ));
+}
+
+#[test]
+fn missing_final_trailer() {
+    // Older writers often leave off the newlines after the last record,
+    // or cut them short, which shouldn't stop the file being indexed.
+    let warc_file = fs::read("tests/example-titles.warc").unwrap();
+    let without_trailer = &warc_file[..warc_file.len() - 4];
+    let expected_index = to_cdxj_string(&indexer(Path::new("tests/example-titles.warc")).unwrap());
+
+    let warc_file_path = std::env::temp_dir().join("wacksy-missing-trailer.warc");
+    for trailer in [b"".as_slice(), b"\r\n", b"\r\n\r"] {
+        fs::write(&warc_file_path, [without_trailer, trailer].concat()).unwrap();
+        let index = indexer(&warc_file_path).unwrap();
+        assert_eq!(
+            to_cdxj_string(&index).replace("wacksy-missing-trailer.warc", "example-titles.warc"),
+            expected_index
+        );
+    }
+
+    // A broken trailer before another record is still an error.
+    let first_record_end = warc_file
+        .windows(9)
+        .position(|window| return window == b"\r\n\r\nWARC/")
+        .unwrap();
+    let broken_trailer = [
+        &warc_file[..first_record_end],
+        &warc_file[first_record_end + 2..],
+    ]
+    .concat();
+    fs::write(&warc_file_path, broken_trailer).unwrap();
+    let result = indexer(&warc_file_path);
+    fs::remove_file(&warc_file_path).unwrap();
+    assert!(matches!(
+        result,
+        Err(IndexerError::RecordError {
+            reason: RecordError::InvalidRecordLength(_),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn lenient_wacz() {
+    let warc_file_path: &Path = Path::new(WARC_CORRUPT_PATH);
+
+    assert!(WACZ::from_files(&[warc_file_path]).is_err());
+
+    let options = WaczOptions {
+        lenient: true,
+        ..Default::default()
+    };
+    let wacz_object = WACZ::from_files_with_options(&[warc_file_path], &options).unwrap();
+    assert_eq!(wacz_object.skipped_regions.len(), 2);
+}
+
+#[test]
+fn post_requests() {
+    let index = indexer(Path::new("tests/example-post.warc.gz")).unwrap();
+    let generated_cdxj_index = to_cdxj_string(&index);
+    let cdxj_lines: Vec<&str> = generated_cdxj_index.lines().collect();
+
+    // Request records are not indexed themselves.
+    assert_eq!(cdxj_lines.len(), 3);
+
+    // The request here comes before the response, and is linked from it.
+    assert!(
+        cdxj_lines[0].starts_with("com,example,forms)/api?__wb_method=post&query=wacz&year=2025 ")
+    );
+
+    // A GET capture keeps the plain key.
+    assert!(cdxj_lines[1].starts_with("com,example,forms)/search 20250601120000 {"));
+    assert!(!cdxj_lines[1].contains("\"method\""));
+
+    // A form POST to the same URL gets its own key, which sorts after it,
+    // with the query parameters in order.
+    assert!(cdxj_lines[2].starts_with(
+        "com,example,forms)/search?__wb_method=post&page=2&q=web+archives 20250601120005 {"
+    ));
+    assert!(cdxj_lines[2].ends_with(r#""method":"POST","requestBody":"q=web+archives&page=2"}"#));
+}
+
+#[test]
+fn url_schemes() {
+    let index = indexer(Path::new("tests/example-schemes.warc.gz")).unwrap();
+    let generated_cdxj_index = to_cdxj_string(&index);
+    let urlkeys: Vec<&str> = generated_cdxj_index
+        .lines()
+        .map(|cdxj_line| return cdxj_line.split(' ').next().unwrap())
+        .collect();
+
+    // Every record is indexed, whatever the scheme of its URL.
+    assert_eq!(urlkeys.len(), 5);
+    assert!(urlkeys.contains(&"com,example,ftp)/pub/readme.txt"));
+    assert!(urlkeys.contains(&"dns:www.example.com"));
+    assert!(urlkeys.contains(&"urn:pageinfo:https://www.example.com/"));
+    assert!(urlkeys.contains(&"mailto:someone@example.com"));
+
+    // data: URIs are keyed by their hash, but the full URI is kept.
+    let data_line = generated_cdxj_index
+        .lines()
+        .find(|cdxj_line| return cdxj_line.starts_with("data:sha256:"))
+        .unwrap();
+    assert!(data_line.contains(r#""url":"data:text/plain;base64,SGVsbG8sIHdvcmxkIQ==""#));
+
+    assert_eq!(
+        create_surt("mailto:someone@example.com")
+            .unwrap_err()
+            .to_string(),
+        "unsupported URL scheme \"mailto\""
+    );
+}
+
+#[test]
+fn urlkeys_with_whitespace() -> Result<(), Box<dyn Error>> {
+    // Keys which aren't canonicalised still can't contain spaces,
+    // or the CDXJ line would be split in the wrong place.
+    let warc_file: String = ["urn:text:https://example.com/my page", "mailto:some one@example.com"]
+        .iter()
+        .map(|url| {
+            return format!(
+                "WARC/1.1\r\nWARC-Type: resource\r\nWARC-Target-URI: {url}\r\nWARC-Date: 2025-09-01T00:00:00Z\r\nContent-Type: text/plain\r\nContent-Length: 5\r\n\r\nhello\r\n\r\n"
+            );
+        })
+        .collect();
+    let warc_file_path = std::env::temp_dir().join("wacksy-whitespace.warc");
+    fs::write(&warc_file_path, warc_file)?;
+    let index = indexer(&warc_file_path)?;
+    fs::remove_file(&warc_file_path)?;
+
+    let generated_cdxj_index = to_cdxj_string(&index);
+    let urlkeys: Vec<&str> = generated_cdxj_index
+        .lines()
+        .map(|cdxj_line| return cdxj_line.split(' ').next().unwrap())
+        .collect();
+    assert_eq!(
+        urlkeys,
+        [
+            "mailto:some%20one@example.com",
+            "urn:text:https://example.com/my%20page"
+        ]
+    );
+
+    // The key can be looked up again, and the URL is kept as it was.
+    let captures = query_index(
+        &index,
+        "urn:text:https://example.com/my page",
+        &QueryOptions::default(),
+    )?;
+    assert_eq!(captures.len(), 1);
+    assert_eq!(
+        captures[0].url(),
+        Some("urn:text:https://example.com/my page")
+    );
+    return Ok(());
+}
+
+#[test]
+fn resource_records() {
+    let index = indexer(Path::new("tests/example-resource.warc.gz")).unwrap();
+    let generated_cdxj_index = to_cdxj_string(&index);
+    let cdxj_lines: Vec<&str> = generated_cdxj_index.lines().collect();
+
+    // Every resource record is indexed with its own mime type, but the
+    // metadata record describing the HTML upload isn't.
+    assert_eq!(cdxj_lines.len(), 3);
+    assert!(!generated_cdxj_index.contains("application/warc-fields"));
+    assert!(cdxj_lines[2].starts_with("urn:view:https://uploads.example.com/ 20250701080000 {"));
+    assert!(cdxj_lines[2].contains(r#""mime":"image/png","offset":0,"length":8,"status":200"#));
+    assert!(cdxj_lines[1].starts_with("urn:text:https://uploads.example.com/ 20250701080001 {"));
+    assert!(cdxj_lines[1].contains(r#""mime":"text/plain""#));
+
+    // An HTML file uploaded to the WARC is a page, but the urn: records aren't.
+    let pages_index = to_pages_json_string(&index);
+    let pages: Vec<&str> = pages_index.lines().skip(1).collect();
+    assert_eq!(pages.len(), 1);
+    assert!(pages[0].contains(r#""url":"https://uploads.example.com/report.html""#));
+}
+
+#[test]
+fn metadata_records() {
+    // A crawler's metadata record shares the URL and date of the
+    // response it describes, and shouldn't be indexed alongside it.
+    let http_block = "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n<html></html>";
+    let metadata_block = "outlink: https://example.com/next L a/@href\r\n";
+    let warc_file = [
+        format!(
+            "WARC/1.1\r\nWARC-Type: response\r\nWARC-Target-URI: https://example.com/\r\nWARC-Date: 2025-09-01T00:00:00Z\r\nWARC-Payload-Digest: sha1:AAAA\r\nContent-Type: application/http; msgtype=response\r\nContent-Length: {}\r\n\r\n{http_block}\r\n\r\n",
+            http_block.len()
+        ),
+        format!(
+            "WARC/1.1\r\nWARC-Type: metadata\r\nWARC-Target-URI: https://example.com/\r\nWARC-Date: 2025-09-01T00:00:00Z\r\nContent-Type: application/warc-fields\r\nContent-Length: {}\r\n\r\n{metadata_block}\r\n\r\n",
+            metadata_block.len()
+        ),
+    ]
+    .concat();
+
+    let warc_file_path = std::env::temp_dir().join("wacksy-metadata.warc");
+    fs::write(&warc_file_path, warc_file).unwrap();
+    let index = indexer(&warc_file_path).unwrap();
+    fs::remove_file(&warc_file_path).unwrap();
+
+    let generated_cdxj_index = to_cdxj_string(&index);
+    let cdxj_lines: Vec<&str> = generated_cdxj_index.lines().collect();
+    assert_eq!(cdxj_lines.len(), 1);
+    assert!(cdxj_lines[0].starts_with("com,example)/ 20250901000000 {"));
+    assert!(cdxj_lines[0].contains(r#""mime":"text/html""#));
+}
+
+#[test]
+fn revisit_records() {
+    let index = indexer(Path::new("tests/example-revisit.warc.gz")).unwrap();
+    let generated_cdxj_index = to_cdxj_string(&index);
+    let cdxj_lines: Vec<&str> = generated_cdxj_index.lines().collect();
+    assert_eq!(cdxj_lines.len(), 4);
+
+    // The original capture is indexed as normal.
+    assert!(cdxj_lines[0].contains(r#""mime":"text/html""#));
+    assert!(!cdxj_lines[0].contains("refersTo"));
+
+    // Identical payload digest, with and without HTTP headers.
+    let refers_to =
+        r#""refersToUrl":"https://dedup.example.com/","refersToDate":"20250801000000"}"#;
+    for cdxj_line in &cdxj_lines[1..3] {
+        assert!(cdxj_line.contains(r#""mime":"warc/revisit""#));
+        assert!(cdxj_line.contains(r#""status":200"#));
+        assert!(cdxj_line.ends_with(refers_to));
+    }
+
+    // Server not modified.
+    assert!(cdxj_lines[3].contains(r#""mime":"warc/revisit""#));
+    assert!(cdxj_lines[3].contains(r#""status":304"#));
+    assert!(cdxj_lines[3].ends_with(refers_to));
+
+    // Only the original capture is a page, even though the revisits
+    // with HTTP headers are text/html too.
+    let pages_index = to_pages_json_string(&index);
+    let pages: Vec<&str> = pages_index.lines().skip(1).collect();
+    assert_eq!(pages.len(), 1);
+    assert!(pages[0].contains(r#""ts":"2025-08-01T00:00:00Z""#));
+}
+
+#[test]
+fn gzip_detected_without_extension() {
+    // Storage systems often rename WARCs, so copy one to a name
+    // which doesn't end in ".gz".
+    let renamed_path = std::env::temp_dir().join("wacksy-example.warc.gz.open");
+    fs::copy(WARC_PATH, &renamed_path).unwrap();
+    let renamed_index = indexer(&renamed_path).unwrap();
+    fs::remove_file(&renamed_path).unwrap();
+
+    let index = indexer(Path::new(WARC_PATH)).unwrap();
+    assert_eq!(renamed_index.len(), index.len());
+    assert_eq!(
+        to_pages_json_string(&renamed_index),
+        to_pages_json_string(&index)
+    );
+}
+
+#[test]
+fn not_a_warc() {
+    let Err(error) = indexer(Path::new("tests/requirements.txt")) else {
+        panic!("expected an error for a file which isn't a WARC");
+    };
+    assert!(matches!(
+        error,
+        IndexerError::UnrecognisedFormatError { .. }
+    ));
+    assert_eq!(error.to_string(), "requirements.txt is not a WARC file");
+}
+
+#[test]
+fn zstd_warc() {
+    let gzip_index = indexer(Path::new(WARC_PATH)).unwrap();
+    let gzip_pages = to_pages_json_string(&gzip_index);
+
+    for zstd_path in [
+        "tests/example.warc.zst",
+        "tests/example-no-dictionary.warc.zst",
+    ] {
+        let zstd_index = indexer(Path::new(zstd_path)).unwrap();
+        assert_eq!(zstd_index.len(), gzip_index.len());
+        assert_eq!(to_pages_json_string(&zstd_index), gzip_pages);
+    }
+
+    // Offsets point at the start of each zstd frame, after the dictionary.
+    let generated_cdxj_index =
+        to_cdxj_string(&indexer(Path::new("tests/example.warc.zst")).unwrap());
+    let warc_bytes = fs::read("tests/example.warc.zst").unwrap();
+    for cdxj_line in generated_cdxj_index.lines() {
+        let (_, json_block) = cdxj_line.split_once(" {").unwrap();
+        let offset: usize = json_block
+            .split_once(r#""offset":"#)
+            .and_then(|(_, rest)| return rest.split_once(','))
+            .map(|(offset, _)| return offset.parse().unwrap())
+            .unwrap();
+        assert!(offset > 0);
+        assert_eq!(warc_bytes[offset..offset + 4], [0x28, 0xb5, 0x2f, 0xfd]);
+        assert!(cdxj_line.contains(r#""filename":"example.warc.zst""#));
+    }
+}
+
+#[test]
+fn zstd_wacz() {
+    let wacz_object = WACZ::from_file(Path::new("tests/example.warc.zst")).unwrap();
+    let datapackage = wacz_object.datapackage.to_string();
+    assert!(datapackage.contains(r#""path":"archive/example.warc.zst""#));
+}
+
+#[test]
+fn large_gzip_record() {
+    use flate2::{Compression, write::GzEncoder};
+    use std::io::Write as _;
+
+    // Write a WARC with a 64 MiB response between two small ones, each
+    // record in its own gzip member. The big one is mostly zeros so it
+    // compresses down to almost nothing.
+    fn gzip_record(warc_file: &mut Vec<u8>, url: &str, body: &[u8]) {
+        let http_block = [
+            b"HTTP/1.1 200 OK\r\nContent-Type: text/html\r\n\r\n".as_slice(),
+            body,
+        ]
+        .concat();
+        let warc_header = format!(
+            "WARC/1.1\r\nWARC-Type: response\r\nWARC-Target-URI: {url}\r\nWARC-Date: 2025-09-01T00:00:00Z\r\nContent-Type: application/http; msgtype=response\r\nContent-Length: {}\r\n\r\n",
+            http_block.len()
+        );
+        let mut encoder = GzEncoder::new(warc_file, Compression::fast());
+        encoder.write_all(warc_header.as_bytes()).unwrap();
+        encoder.write_all(&http_block).unwrap();
+        encoder.write_all(b"\r\n\r\n").unwrap();
+        encoder.finish().unwrap();
+    }
+
+    let mut warc_file = Vec::new();
+    gzip_record(&mut warc_file, "https://example.com/", b"<html></html>");
+    gzip_record(
+        &mut warc_file,
+        "https://example.com/big",
+        &vec![0; 64 << 20],
+    );
+    gzip_record(&mut warc_file, "https://example.com/end", b"<html></html>");
+
+    let warc_file_path = std::env::temp_dir().join("wacksy-large-record.warc.gz");
+    fs::write(&warc_file_path, warc_file).unwrap();
+    let index = indexer(&warc_file_path).unwrap();
+    fs::remove_file(&warc_file_path).unwrap();
+
+    assert_eq!(index.len(), 3);
+    let generated_cdxj_index = to_cdxj_string(&index);
+    let cdxj_lines: Vec<&str> = generated_cdxj_index.lines().collect();
+    assert!(cdxj_lines[1].contains(r#""url":"https://example.com/big""#));
+    assert!(cdxj_lines[2].contains(r#""url":"https://example.com/end""#));
+}
+
+#[test]
+fn non_utf8_headers() {
+    // Header values in Latin-1 are common in older crawls, and
+    // shouldn't stop the record from being indexed.
+    let index = indexer(Path::new("tests/example-latin1.warc")).unwrap();
+    let generated_cdxj_index = to_cdxj_string(&index);
+    let cdxj_lines: Vec<&str> = generated_cdxj_index.lines().collect();
+    assert_eq!(cdxj_lines.len(), 2);
+    assert!(cdxj_lines[0].starts_with("com,example,latin1)/ 20251001000000 {"));
+    assert!(cdxj_lines[1].contains(r#""mime":"text/html","offset":425,"#));
+}
+
+#[test]
+fn parallel_wacz() {
+    let warc_file_paths: Vec<&Path> = [
+        WARC_PATH,
+        WARC_PATH_2,
+        WARC_1_0_PATH,
+        WARC_CORRUPT_PATH,
+        "tests/example-post.warc.gz",
+        "tests/example-revisit.warc.gz",
+        "tests/example.warc.zst",
+    ]
+    .iter()
+    .map(Path::new)
+    .collect();
+
+    let sequential_options = WaczOptions {
+        lenient: true,
+        ..Default::default()
+    };
+    let parallel_options = WaczOptions {
+        lenient: true,
+        threads: 4,
+        ..Default::default()
+    };
+    let sequential_wacz =
+        WACZ::from_files_with_options(&warc_file_paths, &sequential_options).unwrap();
+    let parallel_wacz = WACZ::from_files_with_options(&warc_file_paths, &parallel_options).unwrap();
+
+    // Everything apart from the creation time should be the same.
+    let sequential_resources = &sequential_wacz.datapackage.resources;
+    let parallel_resources = &parallel_wacz.datapackage.resources;
+    assert_eq!(sequential_resources.len(), warc_file_paths.len() + 2);
+    assert_eq!(sequential_resources.len(), parallel_resources.len());
+    for (sequential_resource, parallel_resource) in
+        sequential_resources.iter().zip(parallel_resources)
+    {
+        assert_eq!(
+            sequential_resource.to_string(),
+            parallel_resource.to_string()
+        );
+        assert_eq!(sequential_resource.content, parallel_resource.content);
+    }
+    assert_eq!(
+        format!("{:?}", sequential_wacz.skipped_regions),
+        format!("{:?}", parallel_wacz.skipped_regions)
+    );
+
+    // The first error is from the first file which fails, as it would be
+    // when the files are indexed one at a time.
+    let strict_options = WaczOptions {
+        threads: 4,
+        ..Default::default()
+    };
+    let Err(WaczError::IndexerError(IndexerError::RecordError { file_name, .. })) =
+        WACZ::from_files_with_options(&warc_file_paths, &strict_options)
+    else {
+        panic!("expected an indexer error");
+    };
+    assert_eq!(file_name, "example-corrupt.warc.gz");
+}
+
+#[test]
+fn unusual_http_headers() {
+    let index = indexer(Path::new("tests/example-http.warc.gz")).unwrap();
+    let generated_cdxj_index = to_cdxj_string(&index);
+    let cdxj_lines: Vec<&str> = generated_cdxj_index.lines().collect();
+    assert_eq!(cdxj_lines.len(), 4);
+    assert!(cdxj_lines[0].contains(r#""status":200"#));
+    assert!(cdxj_lines[2].contains(r#""status":404"#));
+    assert!(cdxj_lines[3].contains(r#""status":301"#));
+
+    // Duplicate headers are all kept, and folded lines joined up.
+    let http_headers = index[0].http_headers();
+    assert_eq!(http_headers.len(), 4);
+    assert_eq!(
+        http_headers.get_all("Set-Cookie").collect::<Vec<_>>(),
+        ["a=1", "b=2"]
+    );
+    assert_eq!(http_headers.get("X-Folded"), Some("first second"));
+
+    // Lines which aren't headers are left out.
+    assert_eq!(index[1].http_headers().len(), 1);
+    assert_eq!(
+        index[2].http_headers().get("content-type"),
+        Some("text/html; charset=utf-8")
+    );
+}
+
+/// Write a WARC with a single response record holding this HTTP block.
+fn write_response_warc(file_name: &str, http_block: &[u8]) -> std::path::PathBuf {
+    let warc_header = format!(
+        "WARC/1.1\r\nWARC-Type: response\r\nWARC-Target-URI: https://example.com/\r\nWARC-Date: 2025-09-01T00:00:00Z\r\nContent-Type: application/http; msgtype=response\r\nContent-Length: {}\r\n\r\n",
+        http_block.len()
+    );
+    let warc_file_path = std::env::temp_dir().join(file_name);
+    fs::write(
+        &warc_file_path,
+        [warc_header.as_bytes(), http_block, b"\r\n\r\n"].concat(),
+    )
+    .unwrap();
+    return warc_file_path;
+}
+
+#[test]
+fn lf_http_headers() {
+    // Headers ending in a bare LF blank line are read, and so is the
+    // body after them.
+    let warc_file_path = write_response_warc(
+        "wacksy-lf-headers.warc",
+        b"HTTP/1.1 200 OK\nContent-Type: text/html\nX-Test: lf\n\n<title>LF</title>",
+    );
+    let (index, _) = indexer_with_options(&warc_file_path, &with_titles()).unwrap();
+    fs::remove_file(&warc_file_path).unwrap();
+
+    assert_eq!(index.len(), 1);
+    assert_eq!(index[0].http_status_code(), 200);
+    assert_eq!(index[0].http_headers().len(), 2);
+    assert_eq!(index[0].http_headers().get("x-test"), Some("lf"));
+    assert_eq!(index[0].title(), Some("LF"));
+}
+
+#[test]
+fn unterminated_http_headers() {
+    // A response with no body may have headers which run to the end
+    // of the record without a blank line, with or without a final
+    // line ending.
+    for http_block in [
+        b"HTTP/1.1 302 Found\r\nLocation: /next\r\nContent-Type: text/html\r\n".as_slice(),
+        b"HTTP/1.1 302 Found\r\nLocation: /next\r\nContent-Type: text/html",
+    ] {
+        let warc_file_path = write_response_warc("wacksy-unterminated-headers.warc", http_block);
+        let index = indexer(&warc_file_path).unwrap();
+        fs::remove_file(&warc_file_path).unwrap();
+
+        assert_eq!(index.len(), 1);
+        assert_eq!(index[0].http_status_code(), 302);
+        assert_eq!(index[0].http_headers().get("location"), Some("/next"));
+        assert_eq!(index[0].mime_type().essence(), "text/html");
+    }
+}
+
+#[test]
+fn mime_type_parameters() {
+    let index = indexer(Path::new("tests/example-http.warc.gz")).unwrap();
+    let generated_cdxj_index = to_cdxj_string(&index);
+    let cdxj_lines: Vec<&str> = generated_cdxj_index.lines().collect();
+
+    // Only the essence of the mime type goes in the index,
+    // and the charset is kept alongside it.
+    assert!(cdxj_lines[3].contains(r#""mime":"text/html""#));
+    assert_eq!(index[2].mime_type().charset(), Some("utf-8"));
+    assert!(cdxj_lines[1].contains(r#""mime":"text/html""#));
+    assert_eq!(index[3].mime_type().charset(), Some("ISO-8859-1"));
+
+    // Pages are found whatever parameters their content type has.
+    let pages_index = to_pages_json_string(&index);
+    let pages: Vec<&str> = pages_index.lines().skip(1).collect();
+    assert_eq!(pages.len(), 2);
+    assert!(pages[1].contains(r#""url":"https://http.example.com/latin""#));
+}
+
+/// Keeps anything from one host which isn't a redirect.
+#[derive(Debug)]
+struct NoRedirects {
+    host: &'static str,
+}
+impl PageSelector for NoRedirects {
+    fn is_page(&self, record: &IndexRecord) -> bool {
+        return record.url().starts_with(&format!("https://{}/", self.host))
+            && !(300..=399).contains(&record.http_status_code());
+    }
+}
+
+#[test]
+fn custom_page_selector() {
+    let warc_file_path = Path::new("tests/example-http.warc.gz");
+    let index = indexer(warc_file_path).unwrap();
+    let page_selector = NoRedirects {
+        host: "http.example.com",
+    };
+
+    let pages_index = to_pages_json_string_with_selector(&index, &page_selector);
+    let pages: Vec<&str> = pages_index.lines().skip(1).collect();
+    assert_eq!(pages.len(), 3);
+    assert!(pages[1].contains(r#""url":"https://http.example.com/missing""#));
+
+    // The same pages are written to the WACZ.
+    let options = WaczOptions {
+        page_selector: Arc::new(page_selector),
+        ..Default::default()
+    };
+    let wacz_object = WACZ::from_files_with_options(&[warc_file_path], &options).unwrap();
+    let pages_resource = wacz_object
+        .datapackage
+        .resources
+        .iter()
+        .find(|resource| return resource.path == "pages/pages.jsonl")
+        .unwrap();
+    assert_eq!(pages_resource.content, pages_index.as_bytes());
+}
+
+/// Titles are only read when they're asked for.
+fn with_titles() -> IndexerOptions {
+    return IndexerOptions {
+        extract_titles: true,
+        ..Default::default()
+    };
+}
+
+#[test]
+fn html_titles() {
+    let warc_file_path = Path::new("tests/example-titles.warc");
+    let (index, _) = indexer_with_options(warc_file_path, &with_titles()).unwrap();
+    let titles: Vec<Option<&str>> = index.iter().map(|record| return record.title()).collect();
+    assert_eq!(
+        titles,
+        [
+            // Chunked and gzipped, with character references.
+            Some("Fish & Chips — Menu"),
+            // The charset is only given in a <meta> tag.
+            Some("Café Crème"),
+            // Says it's chunked, but was stored dechunked.
+            Some("Привет"),
+            Some("Deflated"),
+            // Brotli can't be decoded.
+            None,
+            None,
+        ]
+    );
+
+    let pages_index = to_pages_json_string(&index);
+    let pages: Vec<&str> = pages_index.lines().skip(1).collect();
+    assert_eq!(pages.len(), 6);
+    assert!(pages[0].ends_with(r#""ts":"2025-01-01T12:00:00Z","title":"Fish & Chips — Menu"}"#));
+    assert!(pages[5].ends_with(r#""ts":"2025-01-06T12:00:00Z"}"#));
+
+    // Titles are read from compressed WARCs too.
+    let (index, _) = indexer_with_options(Path::new(WARC_PATH), &with_titles()).unwrap();
+    assert!(to_pages_json_string(&index).contains(r#""title":"Example Domain""#));
+
+    // They're off by default, but always read when making a WACZ.
+    let index = indexer(warc_file_path).unwrap();
+    assert!(index.iter().all(|record| return record.title().is_none()));
+    let wacz_object = WACZ::from_files(&[warc_file_path]).unwrap();
+    assert!(find_resource(&wacz_object, "pages/pages.jsonl").contains("Fish & Chips — Menu"));
+
+    // Records the page selector leaves out don't have their title read.
+    let options = IndexerOptions {
+        page_selector: Arc::new(NoRedirects {
+            host: "nowhere.example.com",
+        }),
+        ..with_titles()
+    };
+    let (index, _) = indexer_with_options(warc_file_path, &options).unwrap();
+    assert!(index.iter().all(|record| return record.title().is_none()));
+}
+
+#[test]
+fn page_text() {
+    let options = IndexerOptions {
+        extract_text: true,
+        ..Default::default()
+    };
+    let (index, _) =
+        indexer_with_options(Path::new("tests/example-titles.warc"), &options).unwrap();
+    assert_eq!(index[5].text(), Some("No title"));
+    // Titles are found whether or not there's text.
+    assert_eq!(index[1].title(), Some("Café Crème"));
+    assert_eq!(index[1].text(), None);
+
+    // Text is off by default, and cut short when it's on.
+    let warc_file_path = Path::new("tests/example.warc.gz");
+    let wacz_object = WACZ::from_files(&[warc_file_path]).unwrap();
+    assert!(!find_resource(&wacz_object, "pages/pages.jsonl").contains(r#""text":"#));
+
+    let options = WaczOptions {
+        extract_text: true,
+        max_text_size: 40,
+        ..Default::default()
+    };
+    let wacz_object = WACZ::from_files_with_options(&[warc_file_path], &options).unwrap();
+    let pages_index = find_resource(&wacz_object, "pages/pages.jsonl");
+    let page: serde_json::Value =
+        serde_json::from_str(pages_index.lines().nth(1).unwrap()).unwrap();
+    assert_eq!(page["title"], "Example Domain");
+    assert_eq!(page["text"], "Example Domain This domain is for use in");
+}
+
+fn find_resource(wacz_object: &WACZ, path: &str) -> String {
+    let resource = wacz_object
+        .datapackage
+        .resources
+        .iter()
+        .find(|resource| return resource.path == path)
+        .unwrap();
+    return String::from_utf8(resource.content.clone()).unwrap();
+}
+
+#[test]
+fn page_ids() {
+    // The id is a version 5 UUID of the WARC-Record-ID.
+    let index = indexer(Path::new("tests/example.warc.gz")).unwrap();
+    let pages_index = to_pages_json_string(&index);
+    assert!(pages_index.contains(r#""id":"c3f510ca-0e43-50bb-b4ec-e468d2e819c9""#));
+
+    // Ids don't depend on the order of the files, or on which
+    // other records are listed as pages.
+    let first_path = Path::new("tests/example.warc.gz");
+    let second_path = Path::new("tests/example-http.warc.gz");
+    let page_ids = |warc_file_paths: &[&Path], page_selector: &dyn PageSelector| {
+        let mut index = Vec::new();
+        for warc_file_path in warc_file_paths {
+            index.extend(indexer(warc_file_path).unwrap());
+        }
+        return index
+            .iter()
+            .filter(|record| return page_selector.is_page(record))
+            .map(|record| return (record.url().to_owned(), record.page_id()))
+            .collect::<BTreeMap<String, String>>();
+    };
+    let all_pages = NoRedirects {
+        host: "http.example.com",
+    };
+    let forwards = page_ids(&[first_path, second_path], &HtmlPages);
+    assert_eq!(forwards, page_ids(&[second_path, first_path], &HtmlPages));
+    let everything = page_ids(&[first_path, second_path], &all_pages);
+    for (url, page_id) in &everything {
+        if let Some(html_page_id) = forwards.get(url) {
+            assert_eq!(html_page_id, page_id);
+        }
+    }
+
+    // Every record gets a different id.
+    let mut unique_ids: Vec<String> = index.iter().map(|record| return record.page_id()).collect();
+    unique_ids.sort_unstable();
+    unique_ids.dedup();
+    assert_eq!(unique_ids.len(), index.len());
+}
+
+#[test]
+fn seed_pages() {
+    let warc_file_paths = [
+        Path::new("tests/example.warc.gz"),
+        Path::new("tests/example-http.warc.gz"),
+    ];
+    let options = WaczOptions {
+        seed_urls: vec!["https://example.com".to_owned()],
+        ..Default::default()
+    };
+    let wacz_object = WACZ::from_files_with_options(&warc_file_paths, &options).unwrap();
+
+    let seed_pages = find_resource(&wacz_object, "pages/pages.jsonl");
+    let seed_lines: Vec<&str> = seed_pages.lines().collect();
+    assert_eq!(
+        seed_lines[0],
+        r#"{"format":"json-pages-1.0","id":"pages","title":"Seed Pages"}"#
+    );
+    assert_eq!(seed_lines.len(), 2);
+    assert!(seed_lines[1].contains(r#""url":"https://example.com/""#));
+
+    let extra_pages = find_resource(&wacz_object, "pages/extraPages.jsonl");
+    let extra_lines: Vec<&str> = extra_pages.lines().collect();
+    assert_eq!(
+        extra_lines[0],
+        r#"{"format":"json-pages-1.0","id":"extra-pages","title":"Extra Pages"}"#
+    );
+    assert_eq!(extra_lines.len(), 3);
+    assert!(extra_lines[1].contains(r#""url":"https://http.example.com/""#));
+
+    // Both files are listed in the datapackage, under their own names.
+    let datapackage = wacz_object.datapackage.to_string();
+    assert!(datapackage.contains(r#"{"name":"pages_file","path":"pages/pages.jsonl""#));
+    assert!(datapackage.contains(r#"{"name":"extra_pages_file","path":"pages/extraPages.jsonl""#));
+
+    // Without seeds, there's only the one pages file.
+    let wacz_object = WACZ::from_files(&warc_file_paths).unwrap();
+    assert_eq!(
+        find_resource(&wacz_object, "pages/pages.jsonl")
+            .lines()
+            .count(),
+        4
+    );
+    assert!(
+        !wacz_object
+            .datapackage
+            .resources
+            .iter()
+            .any(|resource| return resource.path == "pages/extraPages.jsonl")
+    );
+}
+
+#[test]
+fn main_page() {
+    let warc_file_paths = [
+        Path::new("tests/example.warc.gz"),
+        Path::new("tests/example-http.warc.gz"),
+    ];
+
+    // Without seeds, the main page is the first page.
+    let wacz_object = WACZ::from_files(&warc_file_paths).unwrap();
+    let datapackage = wacz_object.datapackage.to_string();
+    assert!(datapackage.contains(
+        r#""software":"wacksy 0.3.4","mainPageUrl":"https://example.com/","mainPageDate":"2025-04-23T12:10:42.525Z","resources":["#
+    ));
+
+    // It's the earliest page, whichever order the WARCs are given in.
+    let reversed_paths = [warc_file_paths[1], warc_file_paths[0]];
+    let wacz_object = WACZ::from_files(&reversed_paths).unwrap();
+    assert_eq!(
+        wacz_object.datapackage.main_page_url.as_deref(),
+        Some("https://example.com/")
+    );
+    assert_eq!(
+        wacz_object.datapackage.main_page_date.as_deref(),
+        Some("2025-04-23T12:10:42.525Z")
+    );
+
+    // Otherwise it's the earliest seed page.
+    let options = WaczOptions {
+        seed_urls: vec![
+            "https://http.example.com/".to_owned(),
+            "https://example.com/".to_owned(),
+        ],
+        ..Default::default()
+    };
+    let wacz_object = WACZ::from_files_with_options(&reversed_paths, &options).unwrap();
+    assert_eq!(
+        wacz_object.datapackage.main_page_url.as_deref(),
+        Some("https://example.com/")
+    );
+    assert_eq!(
+        wacz_object.datapackage.main_page_date.as_deref(),
+        Some("2025-04-23T12:10:42.525Z")
+    );
+
+    let options = WaczOptions {
+        seed_urls: vec!["https://http.example.com/latin".to_owned()],
+        ..Default::default()
+    };
+    let wacz_object = WACZ::from_files_with_options(&warc_file_paths, &options).unwrap();
+    assert_eq!(
+        wacz_object.datapackage.main_page_url.as_deref(),
+        Some("https://http.example.com/latin")
+    );
+    assert!(wacz_object.datapackage.main_page_date.is_some());
+
+    // It can be given explicitly, with or without a date.
+    let options = WaczOptions {
+        main_page_url: Some("https://example.com/".to_owned()),
+        ..Default::default()
+    };
+    let wacz_object = WACZ::from_files_with_options(&warc_file_paths, &options).unwrap();
+    assert_eq!(
+        wacz_object.datapackage.main_page_date.as_deref(),
+        Some("2025-04-23T12:10:42.525Z")
+    );
+    let options = WaczOptions {
+        main_page_url: Some("https://example.com/".to_owned()),
+        main_page_date: Some("2025-04-23T00:00:00Z".to_owned()),
+        ..Default::default()
+    };
+    let wacz_object = WACZ::from_files_with_options(&warc_file_paths, &options).unwrap();
+    assert_eq!(
+        wacz_object.datapackage.main_page_date.as_deref(),
+        Some("2025-04-23T00:00:00Z")
+    );
+
+    // A WACZ with no pages has no main page.
+    let options = WaczOptions {
+        page_selector: Arc::new(NoRedirects {
+            host: "nowhere.example.com",
+        }),
+        ..Default::default()
+    };
+    let wacz_object = WACZ::from_files_with_options(&warc_file_paths, &options).unwrap();
+    assert!(!wacz_object.datapackage.to_string().contains("mainPage"));
+}
+
+#[test]
+fn hostile_urls_and_file_names() -> Result<(), Box<dyn Error>> {
+    // Copy the WARC to a file name which needs escaping in JSON.
+    let temporary_directory = std::env::temp_dir().join("wacksy-hostile-file-names");
+    fs::create_dir_all(&temporary_directory)?;
+    let warc_file_name = "quote\"back\\slash\u{1}.warc";
+    let warc_file_path = temporary_directory.join(warc_file_name);
+    fs::copy("tests/example-hostile.warc", &warc_file_path)?;
+
+    let options = WaczOptions {
+        extract_text: true,
+        main_page_url: Some("https://hostile.example.com/say\"hi\"\\now\u{1}".to_owned()),
+        ..Default::default()
+    };
+    let wacz_object = WACZ::from_files_with_options(&[&warc_file_path], &options)?;
+    fs::remove_dir_all(&temporary_directory)?;
+
+    // Every line of the index has a JSON block which
+    // can be read back to get the same values.
+    let index = indexer(Path::new("tests/example-hostile.warc"))?;
+    let cdxj_index = find_resource(&wacz_object, "indexes/index.cdxj");
+    let cdxj_lines: Vec<&str> = cdxj_index.lines().collect();
+    assert_eq!(cdxj_lines.len(), 3);
+    let mut cdxj_urls = Vec::new();
+    for cdxj_line in &cdxj_lines {
+        let json_block = cdxj_line.splitn(3, ' ').nth(2).unwrap();
+        let json_block: serde_json::Value = serde_json::from_str(json_block)?;
+        assert_eq!(json_block["filename"], warc_file_name);
+        if json_block["mime"] == "warc/revisit" {
+            assert_eq!(json_block["refersToUrl"], index[0].url());
+        }
+        cdxj_urls.push(json_block["url"].as_str().unwrap().to_owned());
+    }
+    let mut index_urls: Vec<&str> = index.iter().map(|record| return record.url()).collect();
+    cdxj_urls.sort_unstable();
+    index_urls.sort_unstable();
+    assert_eq!(cdxj_urls, index_urls);
+
+    // Pages, with their titles and text, are valid JSON too.
+    let schema: serde_json::Value =
+        serde_json::from_reader(fs::File::open("tests/schemas/page-record.schema.json")?)?;
+    let validator = jsonschema::validator_for(&schema)?;
+    let pages_index = find_resource(&wacz_object, "pages/pages.jsonl");
+    let page: serde_json::Value = serde_json::from_str(pages_index.lines().nth(1).unwrap())?;
+    assert!(validator.is_valid(&page));
+    assert_eq!(page["url"], index[0].url());
+    assert_eq!(
+        page["title"],
+        "A \"quoted\" \\ title\u{1} with </script> in it"
+    );
+    assert_eq!(page["text"], "Text with \"quotes\" and \\ and \u{7}");
+
+    // As is the datapackage.
+    let datapackage: serde_json::Value =
+        serde_json::from_str(&wacz_object.datapackage.to_string())?;
+    assert_eq!(datapackage["resources"][0]["name"], warc_file_name);
+    assert_eq!(
+        datapackage["resources"][0]["path"],
+        format!("archive/{warc_file_name}")
+    );
+    assert_eq!(datapackage["mainPageUrl"], index[0].url());
+    let datapackage_digest: serde_json::Value =
+        serde_json::from_str(&wacz_object.datapackage_digest.to_string())?;
+    assert_eq!(datapackage_digest["path"], "datapackage.json");
+    return Ok(());
+}
+
+#[test]
+fn sorted_cdxj() {
+    let warc_file_paths = [
+        Path::new("tests/example-revisit.warc.gz"),
+        Path::new("tests/example-resource.warc.gz"),
+        Path::new("tests/example.warc.gz"),
+        Path::new("tests/example-post.warc.gz"),
+        Path::new("tests/example-http.warc.gz"),
+    ];
+    let wacz_object = WACZ::from_files(&warc_file_paths).unwrap();
+    let cdxj_index = find_resource(&wacz_object, "indexes/index.cdxj");
+    let cdxj_lines: Vec<&str> = cdxj_index.lines().collect();
+    assert_eq!(cdxj_lines.len(), 17);
+    assert!(cdxj_lines.is_sorted());
+    assert!(cdxj_lines[0].starts_with("com,example)/ 20250423121042 "));
+
+    // Captures of the same URL are sorted by timestamp.
+    let dedup_timestamps: Vec<&str> = cdxj_lines
+        .iter()
+        .filter(|cdxj_line| return cdxj_line.starts_with("com,example,dedup)/ "))
+        .map(|cdxj_line| return cdxj_line.split(' ').nth(1).unwrap())
+        .collect();
+    assert_eq!(
+        dedup_timestamps,
+        [
+            "20250801000000",
+            "20250802000000",
+            "20250803000000",
+            "20250804000000"
+        ]
+    );
+
+    // Sorting through temporary files gives the same index.
+    let options = WaczOptions {
+        max_sort_memory: 1024,
+        ..Default::default()
+    };
+    let wacz_object = WACZ::from_files_with_options(&warc_file_paths, &options).unwrap();
+    assert_eq!(
+        find_resource(&wacz_object, "indexes/index.cdxj"),
+        cdxj_index
+    );
+
+    let mut index = Vec::new();
+    for warc_file_path in warc_file_paths {
+        index.extend(indexer(warc_file_path).unwrap());
+    }
+    let mut written_cdxj_index = Vec::new();
+    write_cdxj(&index, &mut written_cdxj_index, 0).unwrap();
+    assert_eq!(written_cdxj_index, to_cdxj_string(&index).into_bytes());
+}
+
+#[test]
+fn zipnum_index() {
+    let warc_file_paths = [
+        Path::new("tests/example-resource.warc.gz"),
+        Path::new("tests/example.warc.gz"),
+        Path::new("tests/example-post.warc.gz"),
+        Path::new("tests/example-http.warc.gz"),
+    ];
+    let cdxj_index = find_resource(
+        &WACZ::from_files(&warc_file_paths).unwrap(),
+        "indexes/index.cdxj",
+    );
+    let options = WaczOptions {
+        zipnum_index: true,
+        zipnum_block_size: 4,
+        ..Default::default()
+    };
+    let wacz_object = WACZ::from_files_with_options(&warc_file_paths, &options).unwrap();
+
+    // Both files are listed in the datapackage instead of index.cdxj.
+    let datapackage = wacz_object.datapackage.to_string();
+    assert!(datapackage.contains(r#"{"name":"compressed_index","path":"indexes/index.cdx.gz""#));
+    assert!(datapackage.contains(r#"{"name":"secondary_index","path":"indexes/index.idx""#));
+    assert!(!datapackage.contains("indexes/index.cdxj"));
+
+    let secondary_index = find_resource(&wacz_object, "indexes/index.idx");
+    let secondary_lines: Vec<&str> = secondary_index.lines().collect();
+    assert_eq!(
+        secondary_lines[0],
+        r#"!meta 0 {"format":"cdxj-gzip-1.0","filename":"index.cdx.gz"}"#
+    );
+    // 13 lines in blocks of 4.
+    assert_eq!(secondary_lines.len(), 5);
+
+    let compressed_index = &wacz_object
+        .datapackage
+        .resources
+        .iter()
+        .find(|resource| return resource.path == "indexes/index.cdx.gz")
+        .unwrap()
+        .content;
+    let mut blocks = String::new();
+    for secondary_line in &secondary_lines[1..] {
+        let (block_key, block_location) = secondary_line.split_once(" {").unwrap();
+        let block_location: serde_json::Value =
+            serde_json::from_str(&format!("{{{block_location}")).unwrap();
+        let offset = usize::try_from(block_location["offset"].as_u64().unwrap()).unwrap();
+        let length = usize::try_from(block_location["length"].as_u64().unwrap()).unwrap();
+
+        // Each block is a gzip member which can be read on its own,
+        // and starts with the key in the secondary index.
+        let mut block = String::new();
+        GzDecoder::new(&compressed_index[offset..offset + length])
+            .read_to_string(&mut block)
+            .unwrap();
+        assert!(block.starts_with(&format!("{block_key} {{")));
+        blocks.push_str(&block);
+    }
+    assert_eq!(blocks.trim_end(), cdxj_index);
+}
+
+#[test]
+fn cdxj_queries() -> Result<(), Box<dyn Error>> {
+    let warc_file_paths = [
+        Path::new("tests/example-resource.warc.gz"),
+        Path::new("tests/example.warc.gz"),
+        Path::new("tests/example-post.warc.gz"),
+        Path::new("tests/example-http.warc.gz"),
+    ];
+    let mut index = Vec::new();
+    for warc_file_path in warc_file_paths {
+        index.extend(indexer(warc_file_path)?);
+    }
+    let query = |url: &str, options: &QueryOptions| -> Result<Vec<String>, QueryError> {
+        return Ok(query_index(&index, url, options)?
+            .iter()
+            .map(|capture| return format!("{} {}", capture.urlkey, capture.timestamp))
+            .collect());
+    };
+
+    // The URL is canonicalised, so it matches however it's written.
+    let exact_options = QueryOptions::default();
+    for url in [
+        "https://example.com/",
+        "http://www.example.com",
+        "EXAMPLE.COM",
+    ] {
+        assert_eq!(
+            query(url, &exact_options)?,
+            ["com,example)/ 20250423121042"]
+        );
+    }
+    assert_eq!(
+        query("https://uploads.example.com/report.html", &exact_options)?,
+        ["com,example,uploads)/report.html 20250701080002"]
+    );
+    assert!(query("https://example.com/missing", &exact_options)?.is_empty());
+
+    let prefix_options = QueryOptions {
+        match_type: MatchType::Prefix,
+        ..Default::default()
+    };
+    assert_eq!(
+        query("https://example.com/fav", &prefix_options)?,
+        ["com,example)/favicon.ico 20250423121042"]
+    );
+
+    let host_options = QueryOptions {
+        match_type: MatchType::Host,
+        ..Default::default()
+    };
+    assert_eq!(query("forms.example.com", &host_options)?.len(), 3);
+
+    // The domain includes its subdomains, but not the urn: records.
+    let domain_options = QueryOptions {
+        match_type: MatchType::Domain,
+        ..Default::default()
+    };
+    assert_eq!(query("example.com", &domain_options)?.len(), 10);
+
+    // Shortened timestamps cover the whole of that period.
+    let from_options = QueryOptions {
+        match_type: MatchType::Domain,
+        from: Some("202507".to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(query("example.com", &from_options)?.len(), 5);
+    let to_options = QueryOptions {
+        match_type: MatchType::Domain,
+        to: Some("20250601".to_owned()),
+        ..Default::default()
+    };
+    assert_eq!(query("example.com", &to_options)?.len(), 5);
+
+    let closest_options = QueryOptions {
+        match_type: MatchType::Host,
+        closest: Some("20251101000002".to_owned()),
+        limit: Some(2),
+        ..Default::default()
+    };
+    assert_eq!(
+        query("http.example.com", &closest_options)?,
+        [
+            "com,example,http)/old 20251101000002",
+            "com,example,http)/latin 20251101000003"
+        ]
+    );
+
+    let invalid_options = QueryOptions {
+        from: Some("last year".to_owned()),
+        ..Default::default()
+    };
+    assert!(matches!(
+        query("example.com", &invalid_options),
+        Err(QueryError::InvalidTimestamp(_))
+    ));
+    assert!(matches!(
+        query("mailto:someone@example.com", &exact_options),
+        Err(QueryError::InvalidUrl(_))
+    ));
+    return Ok(());
+}
+
+#[test]
+fn cdxj_query_sources() -> Result<(), Box<dyn Error>> {
+    let warc_file_paths = [
+        Path::new("tests/example-resource.warc.gz"),
+        Path::new("tests/example.warc.gz"),
+        Path::new("tests/example-post.warc.gz"),
+        Path::new("tests/example-http.warc.gz"),
+    ];
+    let mut index = Vec::new();
+    for warc_file_path in warc_file_paths {
+        index.extend(indexer(warc_file_path)?);
+    }
+    let domain_options = QueryOptions {
+        match_type: MatchType::Domain,
+        ..Default::default()
+    };
+
+    // The same captures are found in a CDXJ file, and in a WACZ
+    // with either kind of index.
+    let captures = query_index(&index, "example.com", &domain_options)?;
+    assert_eq!(
+        query_cdxj_file(
+            Path::new("tests/wacz_example/indexes/index.cdxj"),
+            "example.com",
+            &domain_options
+        )?,
+        captures[..2]
+    );
+    let wacz_object = WACZ::from_files(&warc_file_paths)?;
+    assert_eq!(
+        query_wacz(&wacz_object, "example.com", &domain_options)?,
+        captures
+    );
+    let zipnum_options = WaczOptions {
+        zipnum_index: true,
+        zipnum_block_size: 2,
+        ..Default::default()
+    };
+    let wacz_object = WACZ::from_files_with_options(&warc_file_paths, &zipnum_options)?;
+    assert_eq!(
+        query_wacz(&wacz_object, "example.com", &domain_options)?,
+        captures
+    );
+    assert_eq!(
+        query_wacz(
+            &wacz_object,
+            "https://example.com/",
+            &QueryOptions::default()
+        )?[0]
+            .url(),
+        Some("https://example.com/")
+    );
+
+    return Ok(());
+}